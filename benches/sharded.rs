@@ -0,0 +1,83 @@
+//! Compares the sequential engine with the sharded engine on a generated multi-million-row
+//! transactions file. Run as: $ cargo bench --bench sharded [-- rows]
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use toy_payments_engine::{ClientStore, FeeSchedule, LockPolicy, PaymentsEngine, RedisputePolicy,
+    ShardedEngine, Transaction};
+use toy_payments_engine::tx_reader::get_transaction_reader;
+
+const DEFAULT_ROWS: u32 = 4_000_000;
+const CLIENTS: u64 = 10_000;
+
+fn generate_file(fpath: &str, rows: u32) {
+    let mut writer = BufWriter::new(File::create(fpath).unwrap());
+    writeln!(writer, "type,client,tx,amount").unwrap();
+    let mut seed: u64 = 7;
+    for tx_id in 1..=rows {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let client = (seed >> 33) % CLIENTS;
+        let amount = (seed >> 45) % 10_000;
+        match (seed >> 40) % 20 {
+            0..=11 => writeln!(writer, "deposit,{},{},{}.{:04}", client, tx_id, amount, tx_id % 10_000),
+            12..=17 => writeln!(writer, "withdrawal,{},{},{}.{:04}", client, tx_id, amount / 2, tx_id % 10_000),
+            18 => writeln!(writer, "dispute,{},{},", client, tx_id - (seed >> 50) as u32 % tx_id),
+            _ => writeln!(writer, "resolve,{},{},", client, tx_id - (seed >> 50) as u32 % tx_id),
+        }.unwrap();
+    }
+    writer.flush().unwrap();
+}
+
+fn read_transactions(fpath: &str) -> Vec<Transaction> {
+    let mut tx_reader = get_transaction_reader(fpath).unwrap();
+    tx_reader.deserialize::<Transaction>()
+        .flatten()
+        .filter_map(|mut tx| tx.validate().ok().map(|_| tx))
+        .collect()
+}
+
+fn run_sequential(txs: &[Transaction]) -> (Duration, PaymentsEngine) {
+    let start = Instant::now();
+    let mut payments_engine = PaymentsEngine::new();
+    for tx in txs.iter() {
+        let _ = payments_engine.process_transaction(tx);
+    }
+    (start.elapsed(), payments_engine)
+}
+
+fn run_sharded(txs: &[Transaction], shards: usize) -> (Duration, PaymentsEngine) {
+    let start = Instant::now();
+    let mut sharded_engine = ShardedEngine::new(shards, LockPolicy::default(),
+        RedisputePolicy::default(), FeeSchedule::default(), &[]);
+    for tx in txs.iter() {
+        sharded_engine.submit(*tx, ());
+    }
+    let (payments_engine, _) = sharded_engine.finish();
+    (start.elapsed(), payments_engine)
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, a number sets the row count
+    let rows = env::args().skip(1).find_map(|arg| arg.parse().ok()).unwrap_or(DEFAULT_ROWS);
+    let fpath = env::temp_dir().join("toy_payments_engine_bench.csv");
+    let fpath = fpath.to_str().unwrap();
+    generate_file(fpath, rows);
+    let start = Instant::now();
+    let txs = read_transactions(fpath);
+    println!("parsed {} rows in {:?}", txs.len(), start.elapsed());
+    fs::remove_file(fpath).unwrap();
+
+    let (sequential, expected_engine) = run_sequential(&txs);
+    println!("sequential: {:?}", sequential);
+    let cpus = thread::available_parallelism().map_or(4, |cpus| cpus.get());
+    for shards in [2, 4, 8, 16].into_iter().filter(|shards| *shards <= cpus.max(2)) {
+        let (sharded, payments_engine) = run_sharded(&txs, shards);
+        assert_eq!(payments_engine.client_db().clients(), expected_engine.client_db().clients());
+        println!("{} shards: {:?}, speedup {:.2}x", shards, sharded,
+            sequential.as_secs_f64() / sharded.as_secs_f64());
+    }
+}
@@ -0,0 +1,186 @@
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use toy_payments_engine::{AccountRow, AmountFormat, Currency, EngineHandle, EngineClosed, PrecisionPolicy,
+    Transaction};
+use toy_payments_engine::rejects::RejectCause;
+use toy_payments_engine::tx_reader::{parse_json, parse_record, transaction_reader};
+
+/// Outcome of one submitted transaction, `row` is the line number within a CSV batch.
+#[derive(Debug, Serialize)]
+struct OutcomeJson {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    row: Option<u64>,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl OutcomeJson {
+    fn applied(row: Option<u64>) -> OutcomeJson {
+        OutcomeJson{row, outcome: "applied", stage: None, reason: None}
+    }
+
+    fn from_cause(row: Option<u64>, cause: &RejectCause) -> OutcomeJson {
+        OutcomeJson{row, outcome: "rejected", stage: Some(cause.stage()), reason: Some(cause.to_string())}
+    }
+}
+
+/// Query of `GET /clients/{id}`, without a currency the account of transactions without
+/// one is returned.
+#[derive(Debug, Deserialize)]
+struct AccountQuery {
+    currency: Option<Currency>,
+}
+
+type ApiResult<T> = Result<(StatusCode, Json<T>), (StatusCode, String)>;
+
+fn unavailable(err: EngineClosed) -> (StatusCode, String) {
+    (StatusCode::SERVICE_UNAVAILABLE, err.to_string())
+}
+
+/// Routes of the HTTP API over the engine behind the handle.
+pub fn router(handle: EngineHandle) -> Router {
+    Router::new()
+        .route("/transactions", post(post_transaction))
+        .route("/transactions/batch", post(post_batch))
+        .route("/clients", get(get_clients))
+        .route("/clients/{id}", get(get_client))
+        .with_state(handle)
+}
+
+async fn submit(handle: &EngineHandle, row: Option<u64>, parsed: Result<Transaction, RejectCause>)
+        -> Result<OutcomeJson, EngineClosed> {
+    let tx = match parsed {
+        Ok(tx) => tx,
+        Err(cause) => return Ok(OutcomeJson::from_cause(row, &cause)),
+    };
+    match handle.submit(tx).await? {
+        Ok(()) => Ok(OutcomeJson::applied(row)),
+        Err(reason) => Ok(OutcomeJson::from_cause(row, &RejectCause::Engine(reason))),
+    }
+}
+
+/// Applies one JSON transaction, rejected transactions are answered with 422.
+async fn post_transaction(State(handle): State<EngineHandle>, body: Bytes) -> ApiResult<OutcomeJson> {
+    let parsed = parse_json(&String::from_utf8_lossy(&body), PrecisionPolicy::default());
+    if let Err(cause @ RejectCause::ParseJson(_)) = &parsed {
+        return Ok((StatusCode::BAD_REQUEST, Json(OutcomeJson::from_cause(None, cause))));
+    }
+    let outcome = submit(&handle, None, parsed).await.map_err(unavailable)?;
+    let status = match outcome.outcome {
+        "applied" => StatusCode::OK,
+        _ => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    Ok((status, Json(outcome)))
+}
+
+/// Applies a CSV upload row by row in order and reports the outcome of every row.
+async fn post_batch(State(handle): State<EngineHandle>, body: Bytes) -> ApiResult<Vec<OutcomeJson>> {
+    let mut tx_reader = transaction_reader(body.as_ref());
+    let headers = tx_reader.headers().cloned().unwrap_or_default();
+    let mut outcomes = Vec::new();
+    for row in tx_reader.records() {
+        let (line, parsed) = match row {
+            Ok(record) => (record.position().map_or(0, |pos| pos.line()), parse_record(&record, &headers, PrecisionPolicy::default())),
+            Err(err) => (err.position().map_or(0, |pos| pos.line()), Err(RejectCause::Parse(err))),
+        };
+        outcomes.push(submit(&handle, Some(line), parsed).await.map_err(unavailable)?);
+    }
+    Ok((StatusCode::OK, Json(outcomes)))
+}
+
+async fn get_clients(State(handle): State<EngineHandle>) -> ApiResult<Vec<AccountRow>> {
+    let clients = handle.clients().await.map_err(unavailable)?;
+    Ok((StatusCode::OK, Json(clients.iter()
+        .map(|client| AccountRow::new(client, &AmountFormat::default()))
+        .collect())))
+}
+
+async fn get_client(State(handle): State<EngineHandle>, Path(client_id): Path<u16>,
+        Query(query): Query<AccountQuery>) -> ApiResult<AccountRow> {
+    match handle.get_client(client_id, query.currency).await.map_err(unavailable)? {
+        Some(client) => Ok((StatusCode::OK, Json(AccountRow::new(&client, &AmountFormat::default())))),
+        None => Err((StatusCode::NOT_FOUND, format!("unknown client {}", client_id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use toy_payments_engine::{EngineHandle, PaymentsEngine};
+    use crate::http::router;
+
+    /// Sends one HTTP/1.1 request and returns the status code and the JSON body if any.
+    async fn request(addr: &str, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let req = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method, path, addr, body.len(), body);
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+        let (head, body) = resp.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    async fn start_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (handle, _) = EngineHandle::spawn(PaymentsEngine::new(), 16);
+        tokio::spawn(async move { axum::serve(listener, router(handle)).await.unwrap() });
+        addr
+    }
+
+    #[tokio::test]
+    async fn single_transaction_functionality() {
+        let addr = start_server().await;
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}"#;
+        assert_eq!(request(&addr, "POST", "/transactions", deposit).await,
+            (200, json!({"outcome": "applied"})));
+        assert_eq!(request(&addr, "POST", "/transactions", deposit).await,
+            (422, json!({"outcome": "rejected", "stage": "engine", "reason": "duplicate transaction id"})));
+        let withdrawal = r#"{"type": "withdrawal", "client": 1, "tx": 2}"#;
+        assert_eq!(request(&addr, "POST", "/transactions", withdrawal).await,
+            (422, json!({"outcome": "rejected", "stage": "validation", "reason": "amount is missing"})));
+        let (status, outcome) = request(&addr, "POST", "/transactions", "deposit,1,3,1.0").await;
+        assert_eq!(status, 400);
+        assert_eq!(outcome["stage"], "parse");
+
+        assert_eq!(request(&addr, "GET", "/clients/1", "").await, (200, json!({"client": 1,
+            "available": "2.5000", "held": "0.0000", "total": "2.5000", "locked": false})));
+        assert_eq!(request(&addr, "GET", "/clients/2", "").await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn csv_batch_functionality() {
+        let addr = start_server().await;
+        let batch = "type, client, tx, amount, currency\ndeposit, 1, 1, 3.0\nwithdrawal, 1, 2, 5.0\n\
+            refund, 1, 3, 1.0\ndeposit, 2, 4, 1.0\ndispute, 1, 1,\ndeposit, 2, 5, 2.0, EUR\n";
+        let (status, outcomes) = request(&addr, "POST", "/transactions/batch", batch).await;
+        assert_eq!(status, 200);
+        let outcomes = outcomes.as_array().unwrap();
+        assert_eq!(outcomes.len(), 6);
+        assert_eq!(outcomes[0], json!({"row": 2, "outcome": "applied"}));
+        assert_eq!(outcomes[1], json!({"row": 3, "outcome": "rejected", "stage": "engine",
+            "reason": "insufficient funds"}));
+        assert_eq!(outcomes[2]["stage"], "parse");
+        assert_eq!(outcomes[4], json!({"row": 6, "outcome": "applied"}));
+
+        assert_eq!(request(&addr, "GET", "/clients", "").await, (200, json!([
+            {"client": 1, "available": "0.0000", "held": "3.0000", "total": "3.0000", "locked": false},
+            {"client": 2, "available": "1.0000", "held": "0.0000", "total": "1.0000", "locked": false},
+            {"client": 2, "currency": "EUR", "available": "2.0000", "held": "0.0000", "total": "2.0000", "locked": false},
+        ])));
+        assert_eq!(request(&addr, "GET", "/clients/2?currency=eur", "").await.1["total"], "2.0000");
+        assert_eq!(request(&addr, "GET", "/clients/1?currency=EUR", "").await.0, 404);
+        assert_eq!(request(&addr, "GET", "/clients/1?currency=EURO", "").await.0, 400);
+    }
+}
@@ -0,0 +1,88 @@
+mod http;
+mod tcp;
+use std::process::ExitCode;
+
+use clap::Parser;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use toy_payments_engine::{EngineHandle, PolicyArgs};
+
+#[derive(Debug, Parser)]
+#[command(name = "toy-payments-server", version)]
+#[command(about = "Serves the payments engine over HTTP and a TCP line protocol")]
+struct ServerArgs {
+    /// Address to listen on for HTTP requests.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Also accept transaction rows, one per line, on this TCP address.
+    #[arg(long, value_name = "ADDR")]
+    tcp_listen: Option<String>,
+
+    #[command(flatten)]
+    policy: PolicyArgs,
+
+    /// Transactions queued for the engine before requests have to wait.
+    #[arg(long, value_name = "N", default_value_t = 1024)]
+    queue: usize,
+}
+
+async fn bind(addr: &str) -> Option<TcpListener> {
+    match TcpListener::bind(addr).await {
+        Ok(listener) => Some(listener),
+        Err(err) => {
+            eprintln!("error: can not listen on {}: {}", addr, err);
+            None
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = ServerArgs::parse();
+    let payments_engine = match args.policy.build_engine() {
+        Ok(payments_engine) => payments_engine,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(listener) = bind(&args.listen).await else {
+        return ExitCode::FAILURE;
+    };
+    let tcp_listener = match &args.tcp_listen {
+        Some(addr) => match bind(addr).await {
+            Some(listener) => Some(listener),
+            None => return ExitCode::FAILURE,
+        },
+        None => None,
+    };
+    let (handle, join_handle) = EngineHandle::spawn(payments_engine, args.queue);
+
+    let (shutdown_sender, shutdown_receiver) = watch::channel(());
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        let _ = shutdown_sender.send(());
+    });
+    let shutdown = |mut receiver: watch::Receiver<()>| async move {
+        let _ = receiver.changed().await;
+    };
+    if let Some(tcp_listener) = tcp_listener {
+        let tcp_server = tcp::serve(tcp_listener, handle.clone(), shutdown(shutdown_receiver.clone()));
+        tokio::spawn(async move {
+            if let Err(err) = tcp_server.await {
+                eprintln!("error: {}", err);
+            }
+        });
+    }
+    if let Err(err) = axum::serve(listener, http::router(handle.clone()))
+            .with_graceful_shutdown(shutdown(shutdown_receiver)).await {
+        eprintln!("error: {}", err);
+        return ExitCode::FAILURE;
+    }
+    // finish the transactions already queued before exiting
+    let _ = handle.shutdown().await;
+    drop(handle);
+    let _ = join_handle.await;
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,181 @@
+use std::future::Future;
+use std::io;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use toy_payments_engine::{EngineHandle, PrecisionPolicy};
+use toy_payments_engine::rejects::RejectCause;
+use toy_payments_engine::tx_reader::{parse_record, read_line_record};
+
+/// Longest accepted line, longer lines are skipped and answered with a parse nack.
+const MAX_LINE_LEN: u64 = 1024;
+
+/// Accepts feeder connections until `shutdown` completes.
+///
+/// Every connection sends `type,client,tx,amount[,currency[,to]]` rows, one per line, and gets one reply
+/// line per row in the same order: `ack <tx>` for an applied transaction, or
+/// `nack <tx> <stage> <reason>` with `-` as tx for an unparsable row. A row is applied
+/// before the next row of the same connection is read, rows of different connections
+/// interleave. Empty lines and `type,...` header lines are skipped without a reply.
+pub async fn serve<F>(listener: TcpListener, handle: EngineHandle, shutdown: F) -> io::Result<()>
+        where F: Future<Output = ()> {
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            res = listener.accept() => {
+                let (stream, _) = res?;
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    // the feeder going away ends the connection as well
+                    let _ = handle_connection(stream, handle).await;
+                });
+            },
+            _ = &mut shutdown => return Ok(()),
+        }
+    }
+}
+
+fn reply(tx: &str, res: Result<(), RejectCause>) -> String {
+    match res {
+        Ok(()) => format!("ack {}\n", tx),
+        Err(cause) => format!("nack {} {} {}\n", tx, cause.stage(), cause),
+    }
+}
+
+async fn handle_connection(stream: TcpStream, handle: EngineHandle) -> io::Result<()> {
+    let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount", "currency", "to"]);
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if (&mut reader).take(MAX_LINE_LEN).read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        if !line.ends_with(b"\n") && line.len() as u64 == MAX_LINE_LEN {
+            let mut rest = Vec::new();
+            while (&mut reader).take(MAX_LINE_LEN).read_until(b'\n', &mut rest).await? > 0 &&
+                    !rest.ends_with(b"\n") {
+                rest.clear();
+            }
+            writer.write_all(b"nack - parse line too long\n").await?;
+            continue;
+        }
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let (tx, res) = match read_line_record(&line) {
+            Some(Ok(record)) => {
+                if &record[0] == "type" {
+                    continue;
+                }
+                let res = match parse_record(&record, &headers, PrecisionPolicy::default()) {
+                    Ok(tx) => match handle.submit(tx).await {
+                        Ok(outcome) => outcome.map_err(RejectCause::Engine),
+                        // the engine shut down, drop the connection
+                        Err(_) => break,
+                    },
+                    Err(cause) => Err(cause),
+                };
+                let tx = match &res {
+                    Err(RejectCause::Parse(_)) => "-",
+                    _ => record.get(2).unwrap_or("-"),
+                };
+                (tx.to_string(), res)
+            },
+            // a line that is not UTF-8 is answered like any unparsable row
+            Some(Err(err)) => ("-".to_string(), Err(RejectCause::Parse(err))),
+            None => continue,
+        };
+        writer.write_all(reply(&tx, res).as_bytes()).await?;
+        // replies are batched while the feeder keeps sending
+        if reader.buffer().is_empty() {
+            writer.flush().await?;
+        }
+    }
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use toy_payments_engine::{EngineHandle, PaymentsEngine};
+    use crate::tcp::serve;
+
+    async fn start_server() -> (String, EngineHandle) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (handle, _) = EngineHandle::spawn(PaymentsEngine::new(), 16);
+        let server_handle = handle.clone();
+        tokio::spawn(async move { serve(listener, server_handle, std::future::pending()).await.unwrap() });
+        (addr, handle)
+    }
+
+    /// Sends all lines at once and reads the replies until the server closes the stream.
+    async fn feed(addr: &str, lines: impl AsRef<[u8]>) -> Vec<String> {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(lines.as_ref()).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let mut replies = Vec::new();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            replies.push(line);
+        }
+        replies
+    }
+
+    #[tokio::test]
+    async fn ack_nack_functionality() {
+        let (addr, _) = start_server().await;
+        let replies = feed(&addr, "type, client, tx, amount\ndeposit, 1, 1, 2.0\n\n\
+            withdrawal,1,2,5\ndeposit,1,3\nrefund,1,4,1\ndeposit,1,1,1\ndeposit,1,9,1,usd\n").await;
+        assert_eq!(replies.len(), 6);
+        assert_eq!(replies[0], "ack 1");
+        assert_eq!(replies[1], "nack 2 engine insufficient funds");
+        assert_eq!(replies[2], "nack 3 validation amount is missing");
+        assert!(replies[3].starts_with("nack - parse "));
+        assert_eq!(replies[4], "nack 1 engine duplicate transaction id");
+        assert_eq!(replies[5], "ack 9");
+
+        let replies = feed(&addr, &format!("deposit,1,5,{}\ndeposit,1,6,1\n", "1".repeat(5000))).await;
+        assert_eq!(replies, vec!["nack - parse line too long".to_string(), "ack 6".to_string()]);
+
+        // rows are read like the CSV files, a line that is not UTF-8 only fails itself
+        let replies = feed(&addr, b"deposit,1,7,\"1.5\"\ndeposit,1,8,\xff\ndeposit,1,10,\"1,5\"\ndeposit,1,11,1\n").await;
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0], "ack 7");
+        assert!(replies[1].starts_with("nack - parse "));
+        assert!(replies[2].starts_with("nack - parse "));
+        assert_eq!(replies[3], "ack 11");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_connections_functionality() {
+        let (addr, handle) = start_server().await;
+        let mut feeders = Vec::new();
+        for client in 1..=8u32 {
+            let addr = addr.clone();
+            feeders.push(tokio::spawn(async move {
+                // withdrawals only succeed if the deposits before them were applied first
+                let mut lines = String::new();
+                for idx in 0..50 {
+                    let tx = client * 1000 + idx * 2;
+                    lines += &format!("deposit,{},{},1\nwithdrawal,{},{},1\n", client, tx, client, tx + 1);
+                }
+                feed(&addr, &lines).await
+            }));
+        }
+        for feeder in feeders {
+            let replies = feeder.await.unwrap();
+            assert_eq!(replies.len(), 100);
+            assert!(replies.iter().all(|reply| reply.starts_with("ack ")));
+        }
+        let clients = handle.clients().await.unwrap();
+        assert_eq!(clients.len(), 8);
+        assert!(clients.iter().all(|client| client.total == Decimal::ZERO));
+    }
+}
@@ -0,0 +1,342 @@
+use std::fmt;
+
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, Parser, Subcommand};
+use toy_payments_engine::{AmountFormat, OutputFormat, PolicyArgs, PolicyError, PrecisionPolicy};
+use toy_payments_engine::rejects::RejectsFormat;
+use toy_payments_engine::tx_reader::InputFormat;
+
+/// Exit code for invalid rows found by `validate`.
+pub const EXIT_INVALID_ROWS: u8 = 1;
+/// Exit code for bad arguments, the one clap uses.
+pub const EXIT_USAGE: u8 = 2;
+/// Exit code for unreadable inputs.
+pub const EXIT_INPUT: u8 = 3;
+/// Exit code for unwritable outputs: accounts, rejects report or snapshot.
+pub const EXIT_OUTPUT: u8 = 4;
+/// Exit code for engine state that can not be loaded or stored: database, snapshot or
+/// write-ahead log.
+pub const EXIT_STATE: u8 = 5;
+
+#[derive(Debug, Parser)]
+#[command(name = "toy-payments-engine", version)]
+#[command(about = "Applies transactions to client accounts and reports the accounts")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Without a subcommand the arguments of `process` are expected.
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+impl Cli {
+    pub fn into_command(self) -> Command {
+        match self.command {
+            Some(command) => command,
+            None => Command::Process(self.process),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Process the transactions and print the accounts (default).
+    Process(ProcessArgs),
+    /// Only parse and validate the transactions, exit with 1 if any row is invalid.
+    Validate(ValidateArgs),
+    /// Process the transactions in memory and print statistics instead of the accounts.
+    Stats(StatsArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct InputArgs {
+    /// Input files processed in order, `-` reads stdin.
+    #[arg(required = true, value_name = "INPUT")]
+    pub inputs: Vec<String>,
+
+    /// Format of all inputs, by default JSON for `.json`, NDJSON for `.ndjson` and `.jsonl`
+    /// files, CSV otherwise.
+    #[arg(long, value_name = "FORMAT",
+        value_parser = PossibleValuesParser::new(["csv", "json", "ndjson"])
+            .map(|s| s.parse::<InputFormat>().unwrap()))]
+    pub input_format: Option<InputFormat>,
+
+    /// What to do with amounts of more than four decimal places.
+    #[arg(long, value_name = "POLICY", default_value = "round-half-even",
+        value_parser = PossibleValuesParser::new(["reject", "round-half-up", "round-half-even", "truncate", "exact"])
+            .map(|s| s.parse::<PrecisionPolicy>().unwrap()))]
+    pub precision_policy: PrecisionPolicy,
+
+    /// Write the rejected rows with their reasons to this file.
+    #[arg(long, value_name = "FILE")]
+    pub rejects: Option<String>,
+
+    /// Format of the rejects report, by default NDJSON for `.ndjson` and `.jsonl` files, CSV otherwise.
+    #[arg(long, value_name = "FORMAT",
+        value_parser = PossibleValuesParser::new(["csv", "ndjson"])
+            .map(|s| s.parse::<RejectsFormat>().unwrap()))]
+    pub rejects_format: Option<RejectsFormat>,
+}
+
+impl InputArgs {
+    pub fn input_format(&self, fpath: &str) -> InputFormat {
+        self.input_format.unwrap_or_else(|| InputFormat::from_path(fpath))
+    }
+
+    pub fn rejects_format(&self, fpath: &str) -> RejectsFormat {
+        self.rejects_format.unwrap_or_else(|| RejectsFormat::from_path(fpath))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ProcessArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub policy: PolicyArgs,
+
+    /// Write the accounts to this file instead of stdout.
+    #[arg(long, short, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Format of the accounts, by default JSON for `.json`, NDJSON for `.ndjson` and `.jsonl`
+    /// output files, CSV otherwise.
+    #[arg(long, value_name = "FORMAT",
+        value_parser = PossibleValuesParser::new(["csv", "json", "ndjson", "table"])
+            .map(|s| s.parse::<OutputFormat>().unwrap()))]
+    pub format: Option<OutputFormat>,
+
+    /// Decimal places of the amounts in the accounts.
+    #[arg(long, value_name = "N", default_value_t = 4,
+        value_parser = clap::value_parser!(u32).range(0..=28))]
+    pub scale: u32,
+
+    /// Group the thousands of the amounts, with `,` unless another separator is given.
+    #[arg(long, value_name = "CHAR", num_args = 0..=1, default_missing_value = ",")]
+    pub thousands_separator: Option<char>,
+
+    /// Persist clients and transactions in this SQLite database.
+    #[arg(long, value_name = "FILE")]
+    pub db: Option<String>,
+
+    /// Load the engine state from this snapshot before processing.
+    #[arg(long, value_name = "FILE")]
+    pub restore: Option<String>,
+
+    /// Save the engine state to this snapshot after processing.
+    #[arg(long, value_name = "FILE")]
+    pub snapshot: Option<String>,
+
+    /// Log accepted transactions to this write-ahead log and replay it on startup.
+    #[arg(long, value_name = "FILE")]
+    pub wal: Option<String>,
+
+    /// Process the transactions in memory on this many threads, sharded by client id. From the
+    /// first transfer or transaction id reused across shards on, the rest runs on one thread.
+    #[arg(long, value_name = "N", conflicts_with_all = ["db", "restore", "wal"],
+        value_parser = clap::value_parser!(u16).range(1..))]
+    pub shards: Option<u16>,
+}
+
+impl ProcessArgs {
+    pub fn amount_format(&self) -> AmountFormat {
+        AmountFormat{scale: self.scale, thousands_separator: self.thousands_separator}
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        match (self.format, &self.output) {
+            (Some(format), _) => format,
+            (None, Some(fpath)) => OutputFormat::from_path(fpath),
+            (None, None) => OutputFormat::Csv,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub policy: PolicyArgs,
+
+    /// Load the engine state from this snapshot before processing.
+    #[arg(long, value_name = "FILE")]
+    pub restore: Option<String>,
+}
+
+/// Failure of a command, reported on stderr and mapped to the exit code.
+#[derive(Debug)]
+pub enum CliError {
+    InvalidRows(usize),
+    Input(String),
+    Output(String),
+    State(String),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::InvalidRows(_) => EXIT_INVALID_ROWS,
+            CliError::Input(_) => EXIT_INPUT,
+            CliError::Output(_) => EXIT_OUTPUT,
+            CliError::State(_) => EXIT_STATE,
+        }
+    }
+}
+
+impl From<PolicyError> for CliError {
+    fn from(err: PolicyError) -> Self {
+        match err {
+            PolicyError::ApplyProfiles(_) => CliError::State(err.to_string()),
+            _ => CliError::Input(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::InvalidRows(count) => write!(f, "{} invalid rows", count),
+            CliError::Input(msg) => write!(f, "{}", msg),
+            CliError::Output(msg) => write!(f, "{}", msg),
+            CliError::State(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use crate::cli::{Cli, Command, EXIT_USAGE};
+    use toy_payments_engine::{AmountFormat, LockPolicy, OutputFormat, PrecisionPolicy, RedisputePolicy};
+    use toy_payments_engine::rejects::RejectsFormat;
+    use toy_payments_engine::tx_reader::InputFormat;
+
+    #[test]
+    fn parse_args_functionality() {
+        let args = match Cli::parse_from(["engine", "transactions.csv"]).into_command() {
+            Command::Process(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.input.inputs, vec!["transactions.csv".to_string()]);
+        assert_eq!(args.policy.lock_policy, LockPolicy::RejectAll);
+        assert_eq!(args.policy.redispute_policy, RedisputePolicy::Never);
+        assert_eq!(args.input.rejects, None);
+        assert_eq!(args.input.precision_policy, PrecisionPolicy::RoundHalfEven);
+        assert_eq!(args.db, None);
+        assert_eq!(args.restore, None);
+        assert_eq!(args.snapshot, None);
+        assert_eq!(args.wal, None);
+        assert_eq!(args.output, None);
+        assert_eq!(args.output_format(), OutputFormat::Csv);
+        assert_eq!(args.amount_format(), AmountFormat::default());
+        assert_eq!(args.shards, None);
+        assert_eq!(args.policy.fees, None);
+        assert_eq!(args.policy.profiles, None);
+
+        let args = match Cli::parse_from(["engine", "process", "--lock-policy", "allow-disputes",
+                "transactions.csv", "--rejects", "rejects.csv", "--redispute-policy", "after-resolve",
+                "--db", "accounts.sqlite", "--restore", "yesterday.json", "--snapshot", "today.json",
+                "--wal", "engine.wal", "--output", "accounts.csv"]).into_command() {
+            Command::Process(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.input.inputs, vec!["transactions.csv".to_string()]);
+        assert_eq!(args.policy.lock_policy, LockPolicy::AllowDisputes);
+        assert_eq!(args.policy.redispute_policy, RedisputePolicy::AfterResolve);
+        assert_eq!(args.input.rejects, Some("rejects.csv".to_string()));
+        assert_eq!(args.input.rejects_format("rejects.csv"), RejectsFormat::Csv);
+        assert_eq!(args.db, Some("accounts.sqlite".to_string()));
+        assert_eq!(args.restore, Some("yesterday.json".to_string()));
+        assert_eq!(args.snapshot, Some("today.json".to_string()));
+        assert_eq!(args.wal, Some("engine.wal".to_string()));
+        assert_eq!(args.output, Some("accounts.csv".to_string()));
+        assert_eq!(args.output_format(), OutputFormat::Csv);
+
+        let args = match Cli::parse_from(["engine", "transactions.csv", "-o", "accounts.json"]).into_command() {
+            Command::Process(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.output_format(), OutputFormat::Json);
+        let args = match Cli::parse_from(["engine", "transactions.csv", "-o", "accounts.json",
+                "--format", "table"]).into_command() {
+            Command::Process(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.output_format(), OutputFormat::Table);
+
+        let args = match Cli::parse_from(["engine", "transactions.csv", "--scale", "2",
+                "--thousands-separator"]).into_command() {
+            Command::Process(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.amount_format(), AmountFormat{scale: 2, thousands_separator: Some(',')});
+        let args = match Cli::parse_from(["engine", "transactions.csv", "--thousands-separator", "_"])
+                .into_command() {
+            Command::Process(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.amount_format().thousands_separator, Some('_'));
+    }
+
+    #[test]
+    fn parse_args_multiple_inputs_functionality() {
+        let args = match Cli::parse_from(["engine", "validate", "day1.csv", "-", "day3.csv",
+                "--rejects", "rejects.log", "--rejects-format", "ndjson"]).into_command() {
+            Command::Validate(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.input.inputs, vec!["day1.csv".to_string(), "-".to_string(), "day3.csv".to_string()]);
+        assert_eq!(args.input.rejects_format("rejects.log"), RejectsFormat::Ndjson);
+        assert_eq!(args.input.input_format("day1.csv"), InputFormat::Csv);
+        assert_eq!(args.input.input_format("day2.jsonl"), InputFormat::Ndjson);
+
+        let args = match Cli::parse_from(["engine", "stats", "-", "--input-format", "json",
+                "--precision-policy", "reject", "--fees", "fees.json"]).into_command() {
+            Command::Stats(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.policy.fees, Some("fees.json".to_string()));
+        assert_eq!(args.input.input_format("-"), InputFormat::Json);
+        assert_eq!(args.input.precision_policy, PrecisionPolicy::Reject);
+    }
+
+    #[test]
+    fn parse_args_no_inputs() {
+        let err = Cli::try_parse_from(["engine", "--rejects", "rejects.csv"]).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_USAGE as i32);
+        let err = Cli::try_parse_from(["engine", "stats"]).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_USAGE as i32);
+    }
+
+    #[test]
+    fn parse_args_shards() {
+        let args = match Cli::parse_from(["engine", "transactions.csv", "--shards", "4"]).into_command() {
+            Command::Process(args) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.shards, Some(4));
+        let err = Cli::try_parse_from(["engine", "transactions.csv", "--shards", "0"]).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_USAGE as i32);
+        let err = Cli::try_parse_from(["engine", "transactions.csv", "--shards", "4", "--db",
+            "accounts.sqlite"]).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_USAGE as i32);
+    }
+
+    #[test]
+    fn parse_args_unknown_lock_policy() {
+        let err = Cli::try_parse_from(["engine", "transactions.csv", "--lock-policy",
+            "allow-everything"]).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_USAGE as i32);
+    }
+}
@@ -1,13 +1,46 @@
+use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 
+use crate::currency::Currency;
+
+/// Account of a client in one currency, `None` is the currency of transactions without one.
 #[derive(PartialEq)]
 #[derive(Copy, Clone)]
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Client {
     pub id: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
+    /// Fees collected from the account, refunded fees are deducted.
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub fees: Decimal,
     pub locked: bool,
+    /// How far `available` may go below zero.
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub credit_limit: Decimal,
+}
+
+impl Client {
+    /// Empty unlocked account without a credit limit.
+    pub fn new(id: u16, currency: Option<Currency>) -> Client {
+        Client {
+            id,
+            currency,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            total: Decimal::ZERO,
+            fees: Decimal::ZERO,
+            locked: false,
+            credit_limit: Decimal::ZERO,
+        }
+    }
+
+    /// Funds that can be withdrawn or transferred, the available funds plus the credit limit.
+    pub fn spendable(&self) -> Decimal {
+        self.available + self.credit_limit
+    }
 }
 
@@ -1,41 +1,54 @@
 use std::collections::BTreeMap;
 
-use rust_decimal::Decimal;
 use crate::client::Client;
+use crate::currency::Currency;
+use crate::store::ClientStore;
 
+/// In-memory client store, the default storage of the payments engine.
 pub struct ClientDatabase {
-    pub db: BTreeMap<u16, Client>
+    db: BTreeMap<(u16, Option<Currency>), Client>
+}
+
+impl Default for ClientDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ClientDatabase {
-    pub fn get_client(&mut self, client_id: u16) -> Client {
-        let client_maybe = self.db.get(&client_id);
-        if client_maybe.is_some() {
-            client_maybe.unwrap().clone()
+    pub fn new() -> ClientDatabase {
+        ClientDatabase{db: BTreeMap::new()}
+    }
+}
+
+impl ClientStore for ClientDatabase {
+    fn client(&self, client_id: u16, currency: Option<Currency>) -> Option<Client> {
+        self.db.get(&(client_id, currency)).copied()
+    }
+
+    fn get_client(&mut self, client_id: u16, currency: Option<Currency>) -> Client {
+        if let Some(client) = self.db.get(&(client_id, currency)) {
+            *client
         } else {
-            let new_client = Client {
-                id: client_id,
-                available: Decimal::from(0),
-                held: Decimal::from(0),
-                total: Decimal::from(0),
-                locked: false,
-            };
-            self.db.insert(client_id, new_client);
+            let new_client = Client::new(client_id, currency);
+            self.db.insert((client_id, currency), new_client);
             new_client
         }
     }
 
-    pub fn update_client(&mut self, client: &Client) {
-        self.db.insert(client.id, *client);
+    fn update_client(&mut self, client: &Client) {
+        self.db.insert((client.id, client.currency), *client);
     }
-    
-    pub fn print_all(&mut self) {
-        println!("client,available,held,total,locked");
-        for (_, client) in self.db.iter() {
-            let row = format!("{},{},{},{},{}", client.id, client.available, client.held,
-                client.total, client.locked);
-            println!("{}",row);
-        }
+
+    fn clients(&self) -> Vec<Client> {
+        self.db.values().copied().collect()
+    }
+
+    fn client_accounts(&self, client_id: u16) -> Vec<Client> {
+        self.db.range((client_id, None)..)
+            .take_while(|((id, _), _)| *id == client_id)
+            .map(|(_, client)| *client)
+            .collect()
     }
 }
 
@@ -43,23 +56,52 @@ impl ClientDatabase {
 mod tests {
     #[test]
     fn basic_database_functionality() {
-        use std::collections::BTreeMap;
         use rust_decimal::Decimal;
         use crate::client::Client;
         use crate::client_database::ClientDatabase;
+        use crate::store::ClientStore;
 
-        let mut client_db = ClientDatabase{db: BTreeMap::new()};
+        let mut client_db = ClientDatabase::new();
         let mut client = Client{
             id: 1,
+            currency: None,
             available: Decimal::from(0),
             held: Decimal::from(0),
             total: Decimal::from(0),
+            fees: Decimal::ZERO,
             locked: false,
+            credit_limit: Decimal::ZERO,
         };
-        assert_eq!(client_db.get_client(client.id), client);
+        assert_eq!(client_db.client(client.id, None), None);
+        assert_eq!(client_db.get_client(client.id, None), client);
+        assert_eq!(client_db.client(client.id, None), Some(client));
         client.locked = true;
         client_db.update_client(&client);
-        assert_eq!(client_db.get_client(client.id), client);
+        assert_eq!(client_db.get_client(client.id, None), client);
+        assert_eq!(client_db.clients(), vec![client]);
     }
-}
 
+    #[test]
+    fn currency_accounts_functionality() {
+        use rust_decimal::Decimal;
+        use crate::client_database::ClientDatabase;
+        use crate::currency::Currency;
+        use crate::store::ClientStore;
+
+        let eur: Currency = "EUR".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let mut client_db = ClientDatabase::new();
+        let mut account = client_db.get_client(2, Some(usd));
+        account.available = Decimal::from(5);
+        client_db.update_client(&account);
+        client_db.get_client(2, Some(eur));
+        client_db.get_client(1, None);
+        client_db.get_client(3, Some(eur));
+        assert_eq!(client_db.client(2, Some(usd)), Some(account));
+        assert_eq!(client_db.client(2, None), None);
+        let accounts = client_db.client_accounts(2);
+        assert_eq!(accounts.iter().map(|client| client.currency).collect::<Vec<_>>(), vec![Some(eur), Some(usd)]);
+        assert_eq!(client_db.clients().len(), 4);
+        assert!(client_db.client_accounts(4).is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Three-letter currency code like `EUR`, stored uppercase.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // only ASCII letters are ever stored
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code: [u8; 3] = match s.as_bytes().try_into() {
+            Ok(code) => code,
+            Err(_) => return Err(()),
+        };
+        if !code.iter().all(u8::is_ascii_alphabetic) {
+            return Err(());
+        }
+        Ok(Currency(code.map(|letter| letter.to_ascii_uppercase())))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        match code.parse() {
+            Ok(currency) => Ok(currency),
+            Err(()) => Err(de::Error::custom(format!("invalid currency code {}", code))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::currency::Currency;
+
+    #[test]
+    fn currency_code_functionality() {
+        let currency: Currency = "eur".parse().unwrap();
+        assert_eq!(currency.as_str(), "EUR");
+        assert_eq!(currency, "EUR".parse().unwrap());
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("E1R".parse::<Currency>().is_err());
+        assert!("".parse::<Currency>().is_err());
+        assert_eq!(serde_json::to_string(&currency).unwrap(), "\"EUR\"");
+        assert_eq!(serde_json::from_str::<Currency>("\"usd\"").unwrap().to_string(), "USD");
+        assert!(serde_json::from_str::<Currency>("\"dollar\"").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::error::Error;
+use std::fmt;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{self, JoinHandle};
+
+use crate::client::Client;
+use crate::currency::Currency;
+use crate::outcome::ProcessOutcome;
+use crate::payments_engine::PaymentsEngine;
+use crate::store::{ClientStore, TransactionStore};
+use crate::tx::Transaction;
+
+enum Command {
+    Submit(Transaction, oneshot::Sender<ProcessOutcome>),
+    GetClient(u16, Option<Currency>, oneshot::Sender<Option<Client>>),
+    Clients(oneshot::Sender<Vec<Client>>),
+    Shutdown,
+}
+
+/// The engine behind the handle is no longer running: it was shut down or it panicked.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct EngineClosed;
+
+impl fmt::Display for EngineClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "payments engine is closed")
+    }
+}
+
+impl Error for EngineClosed {}
+
+/// Cloneable async handle to a payments engine running as an actor.
+///
+/// The engine owns its stores exclusively and applies the commands one at a time in the
+/// order they were received, so any number of tasks can submit concurrently. The command
+/// channel is bounded: once it is full, `submit` waits until the engine catches up.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct EngineHandle {
+    sender: mpsc::Sender<Command>,
+}
+
+impl EngineHandle {
+    /// Starts the engine on the blocking thread pool of the current tokio runtime, the
+    /// stores may do blocking IO. The returned join handle yields the engine back once it
+    /// was shut down or all handles were dropped.
+    pub fn spawn<C, T>(mut payments_engine: PaymentsEngine<C, T>, capacity: usize)
+            -> (EngineHandle, JoinHandle<PaymentsEngine<C, T>>)
+            where C: ClientStore + Send + 'static, T: TransactionStore + Send + 'static {
+        let (sender, mut receiver) = mpsc::channel(capacity.max(1));
+        let join_handle = task::spawn_blocking(move || {
+            while let Some(command) = receiver.blocking_recv() {
+                match command {
+                    Command::Submit(tx, reply) => {
+                        // the submitter may have gone away, the transaction still counts
+                        let _ = reply.send(payments_engine.process_transaction(&tx));
+                    },
+                    Command::GetClient(client_id, currency, reply) => {
+                        let _ = reply.send(payments_engine.account(client_id, currency));
+                    },
+                    Command::Clients(reply) => {
+                        let _ = reply.send(payments_engine.client_db().clients());
+                    },
+                    // new commands are refused, the queued ones are still processed
+                    Command::Shutdown => receiver.close(),
+                }
+            }
+            payments_engine
+        });
+        (EngineHandle{sender}, join_handle)
+    }
+
+    async fn request<R>(&self, command: impl FnOnce(oneshot::Sender<R>) -> Command)
+            -> Result<R, EngineClosed> {
+        let (reply, response) = oneshot::channel();
+        if self.sender.send(command(reply)).await.is_err() {
+            return Err(EngineClosed);
+        }
+        response.await.map_err(|_| EngineClosed)
+    }
+
+    /// Processes the transaction and returns its outcome. The transaction does not need to be
+    /// validated, a missing amount or destination is rejected.
+    pub async fn submit(&self, tx: Transaction) -> Result<ProcessOutcome, EngineClosed> {
+        self.request(|reply| Command::Submit(tx, reply)).await
+    }
+
+    /// Reads the account of the client in the currency as of all transactions submitted
+    /// before.
+    pub async fn get_client(&self, client_id: u16, currency: Option<Currency>)
+            -> Result<Option<Client>, EngineClosed> {
+        self.request(|reply| Command::GetClient(client_id, currency, reply)).await
+    }
+
+    /// Reads all accounts ordered by client id and currency.
+    pub async fn clients(&self) -> Result<Vec<Client>, EngineClosed> {
+        self.request(Command::Clients).await
+    }
+
+    /// Stops accepting commands, the ones already queued are still processed. Await the
+    /// join handle of `spawn` to wait until they are done.
+    pub async fn shutdown(&self) -> Result<(), EngineClosed> {
+        self.sender.send(Command::Shutdown).await.map_err(|_| EngineClosed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{self, Future};
+    use std::task::Poll;
+    use rust_decimal::Decimal;
+    use tokio::task;
+    use crate::engine_handle::{EngineHandle, EngineClosed};
+    use crate::outcome::RejectReason;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::tx::{Transaction, TransactionType};
+
+    fn deposit(client: u16, tx: u32) -> Transaction {
+        Transaction{tx_type: TransactionType::Deposit, client, tx, amount: Some(Decimal::ONE), currency: None, to: None}
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_submit_functionality() {
+        let (handle, join_handle) = EngineHandle::spawn(PaymentsEngine::new(), 8);
+        let mut tasks = Vec::new();
+        for client in 1..=10u16 {
+            let handle = handle.clone();
+            tasks.push(tokio::spawn(async move {
+                for idx in 0..100u32 {
+                    let tx = client as u32 * 1000 + idx;
+                    assert_eq!(handle.submit(deposit(client, tx)).await, Ok(Ok(())));
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(handle.submit(deposit(1, 1000)).await, Ok(Err(RejectReason::DuplicateTxId)));
+        let client = handle.get_client(3, None).await.unwrap().unwrap();
+        assert_eq!(client.available, Decimal::from(100));
+        assert_eq!(handle.get_client(11, None).await, Ok(None));
+        assert_eq!(handle.clients().await.unwrap().len(), 10);
+
+        drop(handle);
+        let payments_engine = join_handle.await.unwrap();
+        assert_eq!(payments_engine.client(10).unwrap().total, Decimal::from(100));
+    }
+
+    #[tokio::test]
+    async fn unvalidated_submit_functionality() {
+        let (handle, join_handle) = EngineHandle::spawn(PaymentsEngine::new(), 8);
+        let missing_amount = Transaction{amount: None, ..deposit(1, 1)};
+        assert_eq!(handle.submit(missing_amount).await, Ok(Err(RejectReason::MissingAmount)));
+        let missing_to = Transaction{tx_type: TransactionType::Transfer, ..deposit(1, 2)};
+        assert_eq!(handle.submit(missing_to).await, Ok(Err(RejectReason::MissingDestination)));
+
+        // the engine keeps running for all handles
+        let other_handle = handle.clone();
+        assert_eq!(other_handle.submit(deposit(1, 3)).await, Ok(Ok(())));
+        assert_eq!(handle.get_client(1, None).await.unwrap().unwrap().total, Decimal::ONE);
+        drop(handle);
+        drop(other_handle);
+        assert_eq!(join_handle.await.unwrap().client(1).unwrap().total, Decimal::ONE);
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown_functionality() {
+        let (handle, join_handle) = EngineHandle::spawn(PaymentsEngine::new(), 64);
+        let mut submits: Vec<_> = (1..=50u32).map(|tx| Box::pin(handle.submit(deposit(1, tx)))).collect();
+        let mut outcomes = vec![None; submits.len()];
+        // the first poll of a submit queues its transaction, the channel has room for all of
+        // them and the budget of the runtime must not defer any
+        task::unconstrained(future::poll_fn(|context| {
+            for (submit, outcome) in submits.iter_mut().zip(outcomes.iter_mut()) {
+                if let Poll::Ready(val) = submit.as_mut().poll(context) {
+                    *outcome = Some(val);
+                }
+            }
+            Poll::Ready(())
+        })).await;
+        handle.shutdown().await.unwrap();
+        let payments_engine = join_handle.await.unwrap();
+
+        // everything queued before the shutdown was drained
+        for (submit, outcome) in submits.into_iter().zip(outcomes) {
+            let outcome = match outcome {
+                Some(val) => val,
+                None => submit.await,
+            };
+            assert_eq!(outcome, Ok(Ok(())));
+        }
+        assert_eq!(payments_engine.client(1).unwrap().total, Decimal::from(50));
+        assert_eq!(handle.submit(deposit(1, 51)).await, Err(EngineClosed));
+        assert_eq!(handle.get_client(1, None).await, Err(EngineClosed));
+    }
+}
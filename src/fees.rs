@@ -0,0 +1,218 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::tx::{Transaction, TransactionType, AMOUNT_SCALE};
+
+/// Amount band of a tiered fee, `up_to` is inclusive, `None` is the last band which is open.
+#[derive(PartialEq)]
+#[derive(Clone)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeTier {
+    #[serde(default)]
+    pub up_to: Option<Decimal>,
+    #[serde(default)]
+    pub flat: Decimal,
+    #[serde(default)]
+    pub percent: Decimal,
+}
+
+/// Fee of one transaction type: a flat part plus a percentage of the amount, or the flat
+/// and percentage of the first tier the whole amount falls into, capped to `min` and `max`.
+/// A tiered rule has no flat part or percentage of its own, its last tier is open.
+#[derive(PartialEq)]
+#[derive(Clone)]
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeRule {
+    #[serde(default)]
+    pub flat: Decimal,
+    #[serde(default)]
+    pub percent: Decimal,
+    #[serde(default)]
+    pub tiers: Vec<FeeTier>,
+    #[serde(default)]
+    pub min: Option<Decimal>,
+    #[serde(default)]
+    pub max: Option<Decimal>,
+}
+
+impl FeeRule {
+    pub fn fee(&self, amount: Decimal) -> Decimal {
+        // the last tier of a checked rule is open, only a rule without tiers falls through
+        let (flat, percent) = match self.tiers.iter()
+                .find(|tier| tier.up_to.is_none_or(|up_to| amount <= up_to)) {
+            Some(tier) => (tier.flat, tier.percent),
+            None => (self.flat, self.percent),
+        };
+        let mut fee = flat + amount * percent / Decimal::ONE_HUNDRED;
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+        fee.round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::MidpointAwayFromZero)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let values = [Some(self.flat), Some(self.percent), self.min, self.max].into_iter()
+            .chain(self.tiers.iter().flat_map(|tier| [tier.up_to, Some(tier.flat), Some(tier.percent)]));
+        if values.flatten().any(|value| value.is_sign_negative()) {
+            return Err("fees can not be negative".to_string());
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(format!("fee minimum {} is above the maximum {}", min, max));
+            }
+        }
+        if !self.tiers.is_empty() && (!self.flat.is_zero() || !self.percent.is_zero()) {
+            return Err("a tiered fee takes the flat part and percentage from its tiers".to_string());
+        }
+        let up_tos: Vec<Option<Decimal>> = self.tiers.iter().map(|tier| tier.up_to).collect();
+        let ascending = up_tos.windows(2).all(|pair| match pair {
+            [Some(lower), Some(upper)] => lower < upper,
+            [Some(_), None] => true,
+            _ => false,
+        });
+        if !ascending || up_tos.last().is_some_and(Option::is_some) {
+            return Err("fee tiers must be ordered by `up_to`, only the last one is open".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Fees charged on deposits and withdrawals, the other transaction types are free.
+///
+/// Fees are in the currency of the transaction. A deposit is credited without its fee, which
+/// is capped to the deposited amount, a withdrawal debits its fee on top of the amount.
+#[derive(PartialEq)]
+#[derive(Clone)]
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    #[serde(default)]
+    pub deposit: Option<FeeRule>,
+    #[serde(default)]
+    pub withdrawal: Option<FeeRule>,
+}
+
+impl FeeSchedule {
+    /// Loads a schedule from a JSON file, e.g.
+    /// `{"withdrawal": {"flat": "0.5", "percent": "1", "max": "20"}}`.
+    pub fn load<P: AsRef<Path>>(fpath: P) -> io::Result<FeeSchedule> {
+        let schedule: FeeSchedule = serde_json::from_reader(BufReader::new(File::open(fpath)?))?;
+        for rule in [&schedule.deposit, &schedule.withdrawal].into_iter().flatten() {
+            rule.check().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+        Ok(schedule)
+    }
+
+    pub fn fee(&self, tx: &Transaction) -> Decimal {
+        let rule = match tx.tx_type {
+            TransactionType::Deposit => &self.deposit,
+            TransactionType::Withdrawal => &self.withdrawal,
+            _ => &None,
+        };
+        match (rule, tx.amount) {
+            // a deposit never debits the account
+            (Some(rule), Some(amount)) if tx.tx_type == TransactionType::Deposit =>
+                rule.fee(amount).min(amount.max(Decimal::ZERO)),
+            (Some(rule), Some(amount)) => rule.fee(amount),
+            _ => Decimal::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use rust_decimal::Decimal;
+    use crate::fees::{FeeRule, FeeSchedule, FeeTier};
+    use crate::tx::{Transaction, TransactionType};
+
+    #[test]
+    fn fee_rule_functionality() {
+        let rule = FeeRule{flat: Decimal::new(5, 1), percent: Decimal::ONE, min: Some(Decimal::ONE),
+            max: Some(Decimal::from(10)), ..FeeRule::default()};
+        assert_eq!(rule.fee(Decimal::from(10)), Decimal::ONE);
+        assert_eq!(rule.fee(Decimal::from(200)), Decimal::new(25, 1));
+        assert_eq!(rule.fee(Decimal::from(5000)), Decimal::from(10));
+        assert_eq!(rule.fee(Decimal::new(12345, 4)), Decimal::ONE);
+
+        let rule = FeeRule{
+            tiers: vec![
+                FeeTier{up_to: Some(Decimal::from(100)), flat: Decimal::ZERO, percent: Decimal::ZERO},
+                FeeTier{up_to: Some(Decimal::from(1000)), flat: Decimal::ONE, percent: Decimal::ZERO},
+                FeeTier{up_to: None, flat: Decimal::ZERO, percent: Decimal::new(15, 2)},
+            ],
+            ..FeeRule::default()
+        };
+        assert_eq!(rule.fee(Decimal::from(100)), Decimal::ZERO);
+        assert_eq!(rule.fee(Decimal::new(1000001, 4)), Decimal::ONE);
+        assert_eq!(rule.fee(Decimal::new(123456789, 4)), Decimal::new(185185, 4));
+
+        let rule = FeeRule{
+            flat: Decimal::from(2),
+            tiers: vec![FeeTier{up_to: Some(Decimal::from(100)), flat: Decimal::ONE, percent: Decimal::ZERO}],
+            ..FeeRule::default()
+        };
+        assert_eq!(rule.fee(Decimal::from(100)), Decimal::ONE);
+        assert_eq!(rule.fee(Decimal::from(101)), Decimal::from(2));
+        assert!(rule.check().is_err());
+        let rule = FeeRule{
+            flat: Decimal::from(2),
+            tiers: vec![FeeTier{up_to: None, flat: Decimal::ONE, percent: Decimal::ZERO}],
+            ..FeeRule::default()
+        };
+        assert!(rule.check().is_err());
+        assert!(FeeRule{flat: Decimal::ZERO, ..rule}.check().is_ok());
+    }
+
+    #[test]
+    fn fee_schedule_load_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_fees.json");
+        fs::write(&fpath, r#"{
+            "withdrawal": {"flat": "0.5", "percent": 1, "min": "1", "max": "10"},
+            "deposit": {"tiers": [{"up_to": "100"}, {"percent": "0.1"}]}
+        }"#).unwrap();
+        let schedule = FeeSchedule::load(&fpath).unwrap();
+        let mut tx = Transaction{
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::from(500)),
+            currency: None,
+            to: None,
+        };
+        assert_eq!(schedule.fee(&tx), Decimal::new(5, 1));
+        tx.tx_type = TransactionType::Withdrawal;
+        assert_eq!(schedule.fee(&tx), Decimal::new(55, 1));
+        tx.tx_type = TransactionType::Dispute;
+        assert_eq!(schedule.fee(&tx), Decimal::ZERO);
+        // the deposit fee is capped to the amount, the withdrawal fee is not
+        let rule = FeeRule{flat: Decimal::ONE, ..FeeRule::default()};
+        let schedule = FeeSchedule{deposit: Some(rule.clone()), withdrawal: Some(rule)};
+        let small_tx = |tx_type| Transaction{tx_type, amount: Some(Decimal::new(3, 1)), ..tx};
+        assert_eq!(schedule.fee(&small_tx(TransactionType::Deposit)), Decimal::new(3, 1));
+        assert_eq!(schedule.fee(&small_tx(TransactionType::Withdrawal)), Decimal::ONE);
+
+        fs::write(&fpath, r#"{"withdrawal": {"min": "5", "max": "1"}}"#).unwrap();
+        assert!(FeeSchedule::load(&fpath).is_err());
+        fs::write(&fpath, r#"{"deposit": {"tiers": [{"percent": "1"}, {"up_to": "5"}]}}"#).unwrap();
+        assert!(FeeSchedule::load(&fpath).is_err());
+        fs::write(&fpath, r#"{"deposit": {"tiers": [{"up_to": "5"}, {"up_to": "10", "flat": "1"}]}}"#).unwrap();
+        assert!(FeeSchedule::load(&fpath).is_err());
+        fs::write(&fpath, r#"{"withdrawal": {"flat": "-1"}}"#).unwrap();
+        assert!(FeeSchedule::load(&fpath).is_err());
+        fs::write(&fpath, r#"{"refund": {"flat": "1"}}"#).unwrap();
+        assert!(FeeSchedule::load(&fpath).is_err());
+        fs::remove_file(&fpath).unwrap();
+        assert_eq!(FeeSchedule::default().fee(&tx), Decimal::ZERO);
+    }
+}
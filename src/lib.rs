@@ -0,0 +1,58 @@
+//! Toy payments engine: applies deposits, withdrawals, disputes, resolves and chargebacks
+//! to client accounts.
+//!
+//! ```
+//! use rust_decimal::Decimal;
+//! use toy_payments_engine::{PaymentsEngine, Transaction, TransactionType};
+//!
+//! let mut engine = PaymentsEngine::new();
+//! let tx = Transaction{
+//!     tx_type: TransactionType::Deposit,
+//!     client: 1,
+//!     tx: 1,
+//!     amount: Some(Decimal::from(5)),
+//!     currency: None,
+//!     to: None,
+//! };
+//! assert_eq!(engine.process_transaction(&tx), Ok(()));
+//! assert_eq!(engine.client(1).unwrap().available, Decimal::from(5));
+//! ```
+
+pub mod client;
+pub mod currency;
+pub mod client_database;
+pub mod store;
+pub mod tx;
+pub mod tx_database;
+pub mod tx_reader;
+pub mod outcome;
+pub mod payments_engine;
+pub mod rejects;
+pub mod report;
+pub mod sqlite_store;
+pub mod snapshot;
+pub mod wal;
+pub mod sharded;
+pub mod engine_handle;
+pub mod fees;
+pub mod profiles;
+pub mod policy;
+
+pub use client::Client;
+pub use currency::Currency;
+pub use client_database::ClientDatabase;
+pub use store::{ClientStore, TransactionStore};
+pub use tx::{Transaction, TransactionType, ValidationError, PrecisionPolicy};
+pub use tx_database::{TransactionDatabase, StoredTransaction, TxState, RedisputePolicy, Authorization,
+    AuthState};
+pub use outcome::{ProcessOutcome, RejectReason, EngineError};
+pub use report::{AccountRow, AmountFormat, OutputFormat};
+pub use payments_engine::{PaymentsEngine, LockPolicy};
+pub use snapshot::{Snapshot, SnapshotError};
+pub use wal::{WalRecord, WriteAheadLog};
+pub use sharded::ShardedEngine;
+pub use engine_handle::{EngineHandle, EngineClosed};
+pub use fees::{FeeRule, FeeSchedule, FeeTier};
+pub use profiles::{ClientProfile, load_profiles};
+pub use policy::{PolicyArgs, PolicyError};
+pub use sqlite_store::{open_sqlite_stores, SqliteClientStore, SqliteTransactionStore};
@@ -1,29 +1,280 @@
-mod tx_reader;
-use tx_reader::get_transaction_reader;
-mod tx;
-use tx::Transaction;
-mod client;
-mod client_database;
-mod tx_database;
-mod payments_engine;
-use payments_engine::get_payments_engine;
-
-fn main() {
-    let mut payments_engine = get_payments_engine();
-    // process
-    let mut tx_reader = get_transaction_reader();
-    for row in tx_reader.deserialize::<Transaction>() {
+mod cli;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+
+use clap::Parser;
+use rust_decimal::Decimal;
+use cli::{Cli, CliError, Command, InputArgs, ProcessArgs, StatsArgs, ValidateArgs,
+    EXIT_USAGE};
+use toy_payments_engine::{PaymentsEngine, Transaction, ClientStore, TransactionStore, Snapshot,
+    ShardedEngine, AmountFormat, PolicyArgs};
+use toy_payments_engine::sqlite_store::open_sqlite_stores;
+use toy_payments_engine::tx_reader::read_input;
+use toy_payments_engine::rejects::{RejectCause, RejectsWriter};
+
+/// Reads the inputs in order as one stream and hands every row over as the validated
+/// transaction or the reason why the row is unusable.
+fn read_inputs<F>(input_args: &InputArgs, mut handle_row: F) -> Result<(), CliError>
+        where F: FnMut(&str, u64, &str, Result<Transaction, RejectCause>) -> Result<(), CliError> {
+    for input in input_args.inputs.iter() {
+        let rows = match read_input(input, input_args.input_format(input), input_args.precision_policy) {
+            Ok(rows) => rows,
+            Err(err) => return Err(CliError::Input(format!("can not read {}: {}", input, err))),
+        };
+        for row in rows {
+            let row = match row {
+                Ok(row) => row,
+                Err(err) => return Err(CliError::Input(format!("can not read {}: {}", input, err))),
+            };
+            handle_row(input, row.line, &row.record, row.tx)?;
+        }
+    }
+    Ok(())
+}
+
+fn create_rejects_writer(input_args: &InputArgs) -> Result<Option<RejectsWriter>, CliError> {
+    match &input_args.rejects {
+        Some(fpath) => match RejectsWriter::create(fpath, input_args.rejects_format(fpath)) {
+            Ok(writer) => Ok(Some(writer)),
+            Err(err) => Err(CliError::Output(format!("can not create rejects file {}: {}", fpath, err))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn write_reject(rejects_writer: &mut Option<RejectsWriter>, input: &str, line: u64, raw: &str,
+        cause: &RejectCause) -> Result<(), CliError> {
+    if let Some(writer) = rejects_writer.as_mut() {
+        if let Err(err) = writer.write(input, line, raw, cause) {
+            return Err(CliError::Output(format!("can not write rejects file: {}", err)));
+        }
+    }
+    Ok(())
+}
+
+fn flush_rejects(rejects_writer: &mut Option<RejectsWriter>) -> Result<(), CliError> {
+    if let Some(writer) = rejects_writer.as_mut() {
+        if let Err(err) = writer.flush() {
+            return Err(CliError::Output(format!("can not write rejects file: {}", err)));
+        }
+    }
+    Ok(())
+}
+
+fn prepare_engine<C: ClientStore, T: TransactionStore>(payments_engine: &mut PaymentsEngine<C, T>,
+        policy: &PolicyArgs, restore: &Option<String>) -> Result<(), CliError> {
+    policy.configure(payments_engine)?;
+    if let Some(fpath) = restore {
+        match Snapshot::load(fpath) {
+            // a database already holds the state of the snapshots taken before its last record
+            Ok(snapshot) if snapshot.wal_seq < payments_engine.wal_seq() => (),
+            Ok(snapshot) => if let Err(err) = payments_engine.restore(&snapshot) {
+                return Err(CliError::State(format!("can not restore {}: {}", fpath, err)));
+            },
+            Err(err) => return Err(CliError::State(format!("can not restore {}: {}", fpath, err))),
+        }
+    }
+    // profiles override the restored limits
+    match payments_engine.apply_profiles(&policy.client_profiles()?) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(CliError::State(format!("can not apply profiles: {}", err))),
+    }
+}
+
+fn write_accounts<C: ClientStore>(client_db: &C, args: &ProcessArgs) -> Result<(), CliError> {
+    let format = args.output_format();
+    let amount_format = args.amount_format();
+    let res = match &args.output {
+        Some(fpath) => match File::create(fpath) {
+            Ok(file) => {
+                let mut writer = BufWriter::new(file);
+                client_db.write_report(&mut writer, format, &amount_format).and_then(|_| writer.flush())
+            },
+            Err(err) => Err(err),
+        },
+        None => client_db.write_report(&mut io::stdout().lock(), format, &amount_format),
+    };
+    match res {
+        Ok(()) => Ok(()),
+        Err(err) => Err(CliError::Output(format!("can not write accounts: {}", err))),
+    }
+}
+
+fn run_process<C: ClientStore, T: TransactionStore>(mut payments_engine: PaymentsEngine<C, T>,
+        args: ProcessArgs) -> Result<(), CliError> {
+    prepare_engine(&mut payments_engine, &args.policy, &args.restore)?;
+    if let Some(fpath) = &args.wal {
+        if let Err(err) = payments_engine.recover_from_wal(fpath) {
+            return Err(CliError::State(format!("can not recover from write-ahead log {}: {}",
+                fpath, err)));
+        }
+    }
+    let mut rejects_writer = create_rejects_writer(&args.input)?;
+    read_inputs(&args.input, |input, line, raw, row| {
+        let res = match row.map(|tx| payments_engine.try_process_transaction(&tx)) {
+            Ok(Ok(outcome)) => outcome.map_err(RejectCause::Engine),
+            Ok(Err(err)) => return Err(CliError::State(format!("can not process {}:{}: {}",
+                input, line, err))),
+            Err(cause) => Err(cause),
+        };
+        match res {
+            Ok(()) => Ok(()),
+            Err(cause) => write_reject(&mut rejects_writer, input, line, raw, &cause),
+        }
+    })?;
+    flush_rejects(&mut rejects_writer)?;
+    if let Some(fpath) = &args.snapshot {
+        if let Err(err) = payments_engine.checkpoint(fpath) {
+            return Err(CliError::Output(format!("can not write snapshot {}: {}", fpath, err)));
+        }
+    }
+    write_accounts(payments_engine.client_db(), &args)
+}
+
+/// Like `run_process`, but the engine runs on several threads. Rejected rows are collected
+/// and reported in input order once all shards are done.
+fn run_sharded_process(args: ProcessArgs, shard_count: u16) -> Result<(), CliError> {
+    let mut sharded_engine = ShardedEngine::new(shard_count as usize, args.policy.lock_policy,
+        args.policy.redispute_policy, args.policy.fee_schedule()?, &args.policy.client_profiles()?);
+    let mut rejects_writer = create_rejects_writer(&args.input)?;
+    let mut rejected = Vec::new();
+    let mut row_idx = 0;
+    read_inputs(&args.input, |input, line, raw, row| {
+        row_idx += 1;
+        let key = (row_idx, input.to_string(), line, raw.to_string());
         match row {
-            Ok(mut tx) => {
-                if tx.validate() {
-                    payments_engine.process_transaction(&tx);
-                }
+            Ok(tx) => sharded_engine.submit(tx, key),
+            Err(cause) => rejected.push((key, cause)),
+        }
+        Ok(())
+    })?;
+    let (mut payments_engine, engine_rejected) = sharded_engine.finish();
+    rejected.extend(engine_rejected.into_iter().map(|(key, reason)| (key, RejectCause::Engine(reason))));
+    rejected.sort_by_key(|((row_idx, _, _, _), _)| *row_idx);
+    for ((_, input, line, raw), cause) in rejected.iter() {
+        write_reject(&mut rejects_writer, input, *line, raw, cause)?;
+    }
+    flush_rejects(&mut rejects_writer)?;
+    if let Some(fpath) = &args.snapshot {
+        if let Err(err) = payments_engine.checkpoint(fpath) {
+            return Err(CliError::Output(format!("can not write snapshot {}: {}", fpath, err)));
+        }
+    }
+    write_accounts(payments_engine.client_db(), &args)
+}
+
+fn run_validate(args: ValidateArgs) -> Result<(), CliError> {
+    let mut rejects_writer = create_rejects_writer(&args.input)?;
+    let mut invalid_rows = 0;
+    read_inputs(&args.input, |input, line, raw, row| {
+        match row {
+            Ok(_) => Ok(()),
+            Err(cause) => {
+                invalid_rows += 1;
+                eprintln!("{}:{}: {}", input, line, cause);
+                write_reject(&mut rejects_writer, input, line, raw, &cause)
+            },
+        }
+    })?;
+    flush_rejects(&mut rejects_writer)?;
+    if invalid_rows > 0 {
+        return Err(CliError::InvalidRows(invalid_rows));
+    }
+    Ok(())
+}
+
+fn run_stats(args: StatsArgs) -> Result<(), CliError> {
+    let mut payments_engine = PaymentsEngine::new();
+    prepare_engine(&mut payments_engine, &args.policy, &args.restore)?;
+    let mut rejects_writer = create_rejects_writer(&args.input)?;
+    let mut rows = 0;
+    let mut applied: BTreeMap<&'static str, usize> = BTreeMap::new();
+    let mut rejected: BTreeMap<String, usize> = BTreeMap::new();
+    read_inputs(&args.input, |input, line, raw, row| {
+        rows += 1;
+        let res = row.and_then(|tx| payments_engine.process_transaction(&tx)
+            .map(|_| tx.tx_type)
+            .map_err(RejectCause::Engine));
+        match res {
+            Ok(tx_type) => {
+                *applied.entry(tx_type.as_str()).or_default() += 1;
+                Ok(())
+            },
+            Err(cause) => {
+                let key = match &cause {
+                    RejectCause::Parse(_) | RejectCause::ParseJson(_) => cause.stage().to_string(),
+                    _ => format!("{}: {}", cause.stage(), cause),
+                };
+                *rejected.entry(key).or_default() += 1;
+                write_reject(&mut rejects_writer, input, line, raw, &cause)
             },
-            Err(_err) => {
-                //println!("file row parsing error: {}", err);
-            }
         }
+    })?;
+    flush_rejects(&mut rejects_writer)?;
+
+    let clients = payments_engine.client_db().clients();
+    let mut report = format!("rows: {}\n", rows);
+    report += &format!("applied: {}\n", applied.values().sum::<usize>());
+    for (tx_type, count) in applied.iter() {
+        report += &format!("applied {}: {}\n", tx_type, count);
+    }
+    report += &format!("rejected: {}\n", rejected.values().sum::<usize>());
+    for (reason, count) in rejected.iter() {
+        report += &format!("rejected {}: {}\n", reason, count);
+    }
+    // a client has one account per currency
+    let client_ids: BTreeSet<u16> = clients.iter().map(|client| client.id).collect();
+    let locked_ids: BTreeSet<u16> = clients.iter().filter(|client| client.locked).map(|client| client.id).collect();
+    report += &format!("clients: {}\n", client_ids.len());
+    report += &format!("locked clients: {}\n", locked_ids.len());
+    // fees are in the currency of the account
+    let mut fees: BTreeMap<String, Decimal> = BTreeMap::new();
+    for client in clients.iter().filter(|client| !client.fees.is_zero()) {
+        let currency = client.currency.map_or(String::new(), |currency| format!(" {}", currency));
+        *fees.entry(currency).or_default() += client.fees;
+    }
+    for (currency, collected) in fees.iter() {
+        report += &format!("fees collected{}: {}\n", currency, AmountFormat::default().format(*collected));
+    }
+    match io::stdout().lock().write_all(report.as_bytes()) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(CliError::Output(format!("can not write stats: {}", err))),
     }
-    payments_engine.client_db.print_all();
 }
 
+fn run(command: Command) -> Result<(), CliError> {
+    match command {
+        Command::Process(args) => match &args.db {
+            Some(fpath) => match open_sqlite_stores(fpath) {
+                Ok((client_db, tx_db)) => run_process(PaymentsEngine::with_stores(client_db, tx_db), args),
+                Err(err) => Err(CliError::State(format!("can not open database {}: {}", fpath, err))),
+            },
+            None => match args.shards {
+                Some(shard_count) => run_sharded_process(args, shard_count),
+                None => run_process(PaymentsEngine::new(), args),
+            },
+        },
+        Command::Validate(args) => run_validate(args),
+        Command::Stats(args) => run_stats(args),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            let _ = err.print();
+            // --help and --version are reported as errors too
+            return if err.use_stderr() { ExitCode::from(EXIT_USAGE) } else { ExitCode::SUCCESS };
+        }
+    };
+    match run(cli.into_command()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
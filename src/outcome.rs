@@ -0,0 +1,87 @@
+use std::fmt;
+use std::io;
+
+/// Reason why a transaction was not applied by the payments engine.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum RejectReason {
+    /// A transaction with the same id was already processed.
+    DuplicateTxId,
+    /// The withdrawal exceeds the available funds of the client.
+    InsufficientFunds,
+    /// The client is locked and the lock policy does not allow the transaction.
+    AccountLocked,
+    /// The referenced transaction does not exist.
+    UnknownTx,
+    /// The referenced transaction belongs to another client.
+    ClientMismatch,
+    /// The referenced transaction is in another currency than the one given.
+    CurrencyMismatch,
+    /// The referenced transaction is not under dispute.
+    NotUnderDispute,
+    /// The referenced transaction is under dispute, or was disputed before and
+    /// the redispute policy does not allow another dispute.
+    AlreadyDisputed,
+    /// The referenced transaction was charged back, its lifecycle is over.
+    AlreadyChargedBack,
+    /// The referenced transaction type can not be disputed.
+    NotDisputable,
+    /// The transfer goes to the client it comes from.
+    SelfTransfer,
+    /// The referenced authorization was already captured, voided or expired.
+    AuthorizationClosed,
+    /// The capture is larger than the referenced authorization.
+    CaptureExceedsAuthorization,
+    /// The dispute is larger than the amount of the referenced transaction not charged back.
+    DisputeExceedsRemaining,
+    /// The transaction type requires an amount, `Transaction::validate` was skipped.
+    MissingAmount,
+    /// The transfer has no destination client, `Transaction::validate` was skipped.
+    MissingDestination,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            RejectReason::DuplicateTxId => "duplicate transaction id",
+            RejectReason::InsufficientFunds => "insufficient funds",
+            RejectReason::AccountLocked => "account locked",
+            RejectReason::UnknownTx => "unknown transaction",
+            RejectReason::ClientMismatch => "transaction belongs to another client",
+            RejectReason::CurrencyMismatch => "transaction is in another currency",
+            RejectReason::NotUnderDispute => "transaction is not under dispute",
+            RejectReason::AlreadyDisputed => "transaction is already disputed",
+            RejectReason::AlreadyChargedBack => "transaction is already charged back",
+            RejectReason::NotDisputable => "transaction is not disputable",
+            RejectReason::SelfTransfer => "transfer to the same client",
+            RejectReason::AuthorizationClosed => "authorization is already captured or released",
+            RejectReason::CaptureExceedsAuthorization => "capture exceeds the authorization",
+            RejectReason::DisputeExceedsRemaining => "dispute exceeds the amount not charged back",
+            RejectReason::MissingAmount => "amount is missing",
+            RejectReason::MissingDestination => "destination client is missing",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Result of processing a single transaction, `Ok(())` if it was applied.
+pub type ProcessOutcome = Result<(), RejectReason>;
+
+/// Failure of the write-ahead log or the stores, nothing of the transaction was applied.
+#[derive(Debug)]
+pub enum EngineError {
+    Wal(io::Error),
+    Storage(io::Error),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Wal(err) => write!(f, "write-ahead log: {}", err),
+            EngineError::Storage(err) => write!(f, "storage: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
@@ -1,134 +1,682 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
 
 use crate::tx::*;
-use crate::client_database::*;
-use crate::tx_database::TransactionDatabase;
+use crate::client::Client;
+use crate::client_database::ClientDatabase;
+use crate::currency::Currency;
+use crate::fees::FeeSchedule;
+use crate::profiles::ClientProfile;
+use crate::store::{ClientStore, TransactionStore};
+use crate::tx_database::{TransactionDatabase, StoredTransaction, TxState, RedisputePolicy, Authorization,
+    AuthState};
+use crate::outcome::{ProcessOutcome, RejectReason, EngineError};
+use crate::snapshot::{Snapshot, SnapshotError, SNAPSHOT_VERSION};
+use crate::wal::{WalRecord, WriteAheadLog};
+
+/// Why a transaction handler did not apply the transaction.
+#[derive(Debug)]
+enum ApplyError {
+    Reject(RejectReason),
+    Wal(io::Error),
+}
+
+impl From<RejectReason> for ApplyError {
+    fn from(reason: RejectReason) -> Self {
+        ApplyError::Reject(reason)
+    }
+}
+
+type ApplyOutcome = Result<(), ApplyError>;
+
+/// Decides which transactions still apply to a locked (charged back) client.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Default)]
+pub enum LockPolicy {
+    /// The account is frozen, every transaction is rejected. Credit limits can still be
+    /// set and authorizations still be released under every policy.
+    #[default]
+    RejectAll,
+    /// Only deposits are accepted, funds can not leave the account.
+    AllowDeposits,
+    /// Only disputes, resolves and chargebacks of earlier transactions are accepted.
+    AllowDisputes,
+}
+
+impl LockPolicy {
+    pub fn allows(&self, tx_type: TransactionType) -> bool {
+        // credit limits are set by admins, releasing a hold moves no funds out
+        if tx_type == TransactionType::Limit || tx_type == TransactionType::Void ||
+                tx_type == TransactionType::Expire {
+            return true;
+        }
+        match self {
+            LockPolicy::RejectAll => false,
+            // a transfer debits the locked client
+            LockPolicy::AllowDeposits => tx_type == TransactionType::Deposit,
+            LockPolicy::AllowDisputes => tx_type == TransactionType::Dispute ||
+                tx_type == TransactionType::Resolve ||
+                tx_type == TransactionType::Chargeback,
+        }
+    }
+}
+
+impl FromStr for LockPolicy {
+    type Err = ();
 
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject-all" => Ok(LockPolicy::RejectAll),
+            "allow-deposits" => Ok(LockPolicy::AllowDeposits),
+            "allow-disputes" => Ok(LockPolicy::AllowDisputes),
+            _ => Err(()),
+        }
+    }
+}
 
-pub struct PaymentsEngine {
-    pub client_db: ClientDatabase,
-    pub tx_db: TransactionDatabase,
+/// Payments engine over pluggable client and transaction stores, in-memory by default.
+pub struct PaymentsEngine<C: ClientStore = ClientDatabase, T: TransactionStore = TransactionDatabase> {
+    client_db: C,
+    tx_db: T,
+    lock_policy: LockPolicy,
+    redispute_policy: RedisputePolicy,
+    fee_schedule: FeeSchedule,
+    wal: Option<WriteAheadLog>,
+    wal_seq: u64,
+    /// Sequence number of the write-ahead log record being replayed.
+    replay_seq: Option<u64>,
+    /// Sequence number of the record logged for the transaction being applied, it becomes
+    /// `wal_seq` once the stores committed.
+    logged_seq: Option<u64>,
 }
 
-pub fn get_payments_engine() -> PaymentsEngine {
-    let engine = PaymentsEngine{
-        client_db: ClientDatabase{db: BTreeMap::new()},
-        tx_db: TransactionDatabase{db: BTreeMap::new(), disputes: BTreeSet::new()},
-    };
-    engine
+impl Default for PaymentsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PaymentsEngine {
-    fn process_deposit(&mut self, tx: &Transaction) {
-        let mut client = self.client_db.get_client(tx.client);
-        if self.tx_db.is_tx_exists(&tx.tx) {
-            return;
-        }
-        client.available += tx.amount.unwrap();
-        client.total += tx.amount.unwrap();
-        self.tx_db.add_tx(tx);
+    pub fn new() -> PaymentsEngine {
+        PaymentsEngine::with_stores(ClientDatabase::new(), TransactionDatabase::new())
+    }
+}
+
+impl<C: ClientStore, T: TransactionStore> PaymentsEngine<C, T> {
+    pub fn with_stores(client_db: C, tx_db: T) -> PaymentsEngine<C, T> {
+        let wal_seq = tx_db.wal_seq();
+        PaymentsEngine{
+            client_db,
+            tx_db,
+            lock_policy: LockPolicy::default(),
+            redispute_policy: RedisputePolicy::default(),
+            fee_schedule: FeeSchedule::default(),
+            wal: None,
+            wal_seq,
+            replay_seq: None,
+            logged_seq: None,
+        }
+    }
+
+    pub fn lock_policy(&self) -> LockPolicy {
+        self.lock_policy
+    }
+
+    pub fn set_lock_policy(&mut self, lock_policy: LockPolicy) {
+        self.lock_policy = lock_policy;
+    }
+
+    pub fn redispute_policy(&self) -> RedisputePolicy {
+        self.redispute_policy
+    }
+
+    pub fn set_redispute_policy(&mut self, redispute_policy: RedisputePolicy) {
+        self.redispute_policy = redispute_policy;
+    }
+
+    pub fn fee_schedule(&self) -> &FeeSchedule {
+        &self.fee_schedule
+    }
+
+    pub fn set_fee_schedule(&mut self, fee_schedule: FeeSchedule) {
+        self.fee_schedule = fee_schedule;
+    }
+
+    /// Sequence number of the last write-ahead log record applied.
+    pub fn wal_seq(&self) -> u64 {
+        self.wal_seq
+    }
+
+    pub fn client_db(&self) -> &C {
+        &self.client_db
+    }
+
+    pub fn tx_db(&self) -> &T {
+        &self.tx_db
+    }
+
+    /// Returns the account of the client for transactions without a currency.
+    pub fn client(&self, client_id: u16) -> Option<Client> {
+        self.client_db.client(client_id, None)
+    }
+
+    pub fn account(&self, client_id: u16, currency: Option<Currency>) -> Option<Client> {
+        self.client_db.client(client_id, currency)
+    }
+
+    /// A chargeback locks all accounts of the client.
+    fn is_locked(&self, client_id: u16) -> bool {
+        self.client_db.client_accounts(client_id).iter().any(|client| client.locked)
+    }
+
+    /// Returns the account of the client in the currency, a new account of a locked
+    /// client is locked as well. Nothing is stored until the account is updated.
+    fn get_account(&self, client_id: u16, currency: Option<Currency>) -> Client {
+        let mut client = match self.client_db.client(client_id, currency) {
+            Some(client) => client,
+            None => Client::new(client_id, currency),
+        };
+        if !client.locked && self.is_locked(client_id) {
+            client.locked = true;
+        }
+        client
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            wal_seq: self.wal_seq,
+            clients: self.client_db.clients(),
+            transactions: self.tx_db.transactions(),
+            authorizations: self.tx_db.authorizations(),
+        }
+    }
+
+    /// Loads the snapshot into the stores, overwriting clients and transactions with the
+    /// same ids.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), EngineError> {
+        self.tx_db.begin().map_err(EngineError::Storage)?;
+        for client in snapshot.clients.iter() {
+            self.client_db.update_client(client);
+        }
+        for stored_tx in snapshot.transactions.iter() {
+            self.tx_db.add_tx(&stored_tx.tx, stored_tx.fee);
+            self.tx_db.set_state(&stored_tx.tx.tx, stored_tx.state);
+            self.tx_db.set_dispute_amounts(&stored_tx.tx.tx, stored_tx.disputed,
+                stored_tx.charged_back);
+        }
+        for authorization in snapshot.authorizations.iter() {
+            self.tx_db.set_authorization(authorization);
+        }
+        self.tx_db.set_wal_seq(snapshot.wal_seq);
+        self.tx_db.commit().map_err(EngineError::Storage)?;
+        self.wal_seq = snapshot.wal_seq;
+        Ok(())
+    }
+
+    /// Replays the write-ahead log records not covered by the restored snapshot or already
+    /// applied to the stores and keeps logging every accepted transaction to it. Returns the
+    /// number of replayed records.
+    ///
+    /// The engine must be configured with the same policies, fee schedule and profiles as
+    /// the run that wrote the log, a record it rejects fails the recovery.
+    pub fn recover_from_wal<P: AsRef<Path>>(&mut self, fpath: P) -> io::Result<usize> {
+        let (wal, records) = WriteAheadLog::open(fpath)?;
+        self.wal = None;
+        let mut replayed = 0;
+        for record in records.iter() {
+            if record.seq <= self.wal_seq {
+                continue;
+            }
+            // only accepted transactions were logged, the replay must accept them again
+            self.replay_seq = Some(record.seq);
+            let res = self.try_process_transaction(&record.tx);
+            self.replay_seq = None;
+            match res {
+                Ok(Ok(())) => replayed += 1,
+                Ok(Err(reason)) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "record {} is rejected on replay: {}", record.seq, reason))),
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+        self.wal = Some(wal);
+        Ok(replayed)
+    }
+
+    /// Saves a snapshot and drops the write-ahead log records it covers.
+    pub fn checkpoint<P: AsRef<Path>>(&mut self, fpath: P) -> Result<(), SnapshotError> {
+        self.snapshot().save(fpath)?;
+        if let Some(wal) = self.wal.as_mut() {
+            wal.truncate()?;
+        }
+        Ok(())
+    }
+
+    /// Transaction ids are unique across transactions and authorizations.
+    fn is_tx_id_taken(&self, tx_id: u32) -> bool {
+        self.tx_db.is_tx_exists(&tx_id) || self.tx_db.get_authorization(&tx_id).is_some()
+    }
+
+    /// Logs the accepted transaction before any store is updated. The stores record the
+    /// sequence number with the updates, so a record is not replayed after they committed.
+    fn write_ahead(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let seq = match (self.wal.as_mut(), self.replay_seq) {
+            (Some(wal), _) => {
+                let record = WalRecord{seq: self.wal_seq + 1, tx: *tx};
+                if let Err(err) = wal.append(&record) {
+                    return Err(ApplyError::Wal(err));
+                }
+                record.seq
+            },
+            // a replayed record is in the log already
+            (None, Some(seq)) => seq,
+            (None, None) => return Ok(()),
+        };
+        self.logged_seq = Some(seq);
+        self.tx_db.set_wal_seq(seq);
+        Ok(())
+    }
+
+    fn process_deposit(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let mut client = self.get_account(tx.client, tx.currency);
+        if self.is_tx_id_taken(tx.tx) {
+            return Err(RejectReason::DuplicateTxId.into());
+        }
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        // the fee is deducted from the deposited amount
+        let fee = self.fee_schedule.fee(tx);
+        let credit = amount - fee;
+        if credit < Decimal::ZERO {
+            return Err(RejectReason::InsufficientFunds.into());
+        }
+        client.available += credit;
+        client.total += credit;
+        client.fees += fee;
+        self.write_ahead(tx)?;
+        self.tx_db.add_tx(tx, fee);
         self.client_db.update_client(&client);
+        Ok(())
     }
 
-    fn process_withdrawal(&mut self, tx: &Transaction) {
-        let mut client = self.client_db.get_client(tx.client);
-        if self.tx_db.is_tx_exists(&tx.tx) {
-            return;
+    fn process_withdrawal(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let mut client = self.get_account(tx.client, tx.currency);
+        if self.is_tx_id_taken(tx.tx) {
+            return Err(RejectReason::DuplicateTxId.into());
         }
-        let amount = tx.amount.unwrap();
-        if amount <= client.available {
-            client.available -= amount;
-            client.total -= amount;
-            self.tx_db.add_tx(tx);
-        } else {
-            return;
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        // the fee is debited on top of the withdrawn amount
+        let fee = self.fee_schedule.fee(tx);
+        let debit = amount + fee;
+        if debit > client.spendable() {
+            return Err(RejectReason::InsufficientFunds.into());
         }
+        client.available -= debit;
+        client.total -= debit;
+        client.fees += fee;
+        self.write_ahead(tx)?;
+        self.tx_db.add_tx(tx, fee);
         self.client_db.update_client(&client);
+        Ok(())
+    }
+
+    /// Debits the client and credits the destination client in one step. A locked
+    /// destination receives the transfer only if the lock policy allows deposits.
+    fn process_transfer(&mut self, tx: &Transaction) -> ApplyOutcome {
+        // the source and destination would be two copies of the same account
+        if tx.to == Some(tx.client) {
+            return Err(RejectReason::SelfTransfer.into());
+        }
+        let mut source = self.get_account(tx.client, tx.currency);
+        if self.is_tx_id_taken(tx.tx) {
+            return Err(RejectReason::DuplicateTxId.into());
+        }
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        let to = tx.to.ok_or(RejectReason::MissingDestination)?;
+        let mut destination = self.get_account(to, tx.currency);
+        if destination.locked && !self.lock_policy.allows(TransactionType::Deposit) {
+            return Err(RejectReason::AccountLocked.into());
+        }
+        if amount > source.spendable() {
+            return Err(RejectReason::InsufficientFunds.into());
+        }
+        source.available -= amount;
+        source.total -= amount;
+        destination.available += amount;
+        destination.total += amount;
+        self.write_ahead(tx)?;
+        self.tx_db.add_tx(tx, Decimal::ZERO);
+        self.client_db.update_client(&source);
+        self.client_db.update_client(&destination);
+        Ok(())
     }
 
-    fn process_dispute(&mut self, tx: &Transaction) {
-        let mut client = self.client_db.get_client(tx.client);
+    /// Sets the credit limit of the account, a limit below the current overdraft only stops
+    /// further debits.
+    pub fn set_credit_limit(&mut self, client_id: u16, currency: Option<Currency>, credit_limit: Decimal) {
+        let mut client = self.get_account(client_id, currency);
+        client.credit_limit = credit_limit;
+        self.client_db.update_client(&client);
+    }
 
-        if self.tx_db.is_under_dispute(&tx.tx) {
-            return;
+    /// Applies the credit limits of the profiles, the accounts are created if needed.
+    pub fn apply_profiles(&mut self, profiles: &[ClientProfile]) -> Result<(), EngineError> {
+        self.tx_db.begin().map_err(EngineError::Storage)?;
+        for profile in profiles.iter() {
+            self.set_credit_limit(profile.client, profile.currency, profile.credit_limit);
         }
-        let disputed_tx = match self.tx_db.get_tx(&tx.tx) {
-            Some(val) => {
-                self.tx_db.create_dispute(&tx.tx);
-                val
-            },
-            None => return,
+        self.tx_db.commit().map_err(EngineError::Storage)
+    }
+
+    fn process_limit(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let mut client = self.get_account(tx.client, tx.currency);
+        if self.is_tx_id_taken(tx.tx) {
+            return Err(RejectReason::DuplicateTxId.into());
+        }
+        client.credit_limit = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        self.write_ahead(tx)?;
+        self.tx_db.add_tx(tx, Decimal::ZERO);
+        self.client_db.update_client(&client);
+        Ok(())
+    }
+
+    /// Holds the amount, the available funds and the credit limit must cover it.
+    fn process_authorize(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let mut client = self.get_account(tx.client, tx.currency);
+        if self.is_tx_id_taken(tx.tx) {
+            return Err(RejectReason::DuplicateTxId.into());
+        }
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        if amount > client.spendable() {
+            return Err(RejectReason::InsufficientFunds.into());
+        }
+        client.available -= amount;
+        client.held += amount;
+        self.write_ahead(tx)?;
+        let authorization = Authorization{tx: *tx, state: AuthState::Authorized, captured: Decimal::ZERO};
+        self.tx_db.set_authorization(&authorization);
+        self.client_db.update_client(&client);
+        Ok(())
+    }
+
+    /// Returns the open authorization referenced by a capture, void or expire.
+    fn get_open_authorization(&mut self, tx: &Transaction) -> Result<Authorization, RejectReason> {
+        let authorization = match self.tx_db.get_authorization(&tx.tx) {
+            Some(val) => val,
+            None => return Err(RejectReason::UnknownTx),
+        };
+        if authorization.tx.client != tx.client {
+            return Err(RejectReason::ClientMismatch);
+        }
+        if tx.currency.is_some() && tx.currency != authorization.tx.currency {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        if authorization.state != AuthState::Authorized {
+            return Err(RejectReason::AuthorizationClosed);
+        }
+        Ok(authorization)
+    }
+
+    /// Withdraws the captured amount from the held funds and releases the rest. The capture
+    /// is stored as a withdrawal under the id of the authorization, so it is charged the
+    /// withdrawal fee and can be disputed.
+    fn process_capture(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let mut authorization = self.get_open_authorization(tx)?;
+        let authorized = authorization.tx.amount.unwrap();
+        let captured = tx.amount.unwrap_or(authorized);
+        if captured > authorized {
+            return Err(RejectReason::CaptureExceedsAuthorization.into());
+        }
+        let withdrawal = Transaction{
+            tx_type: TransactionType::Withdrawal,
+            amount: Some(captured),
+            currency: authorization.tx.currency,
+            ..authorization.tx
+        };
+        let mut client = self.get_account(tx.client, authorization.tx.currency);
+        // the fee is debited on top of the captured amount, from the released funds first
+        let fee = self.fee_schedule.fee(&withdrawal);
+        if fee > client.spendable() + authorized - captured {
+            return Err(RejectReason::InsufficientFunds.into());
+        }
+        client.held -= authorized;
+        client.available += authorized - captured - fee;
+        client.total -= captured + fee;
+        client.fees += fee;
+        authorization.state = AuthState::Captured;
+        authorization.captured = captured;
+        self.write_ahead(tx)?;
+        self.tx_db.set_authorization(&authorization);
+        self.tx_db.add_tx(&withdrawal, fee);
+        self.client_db.update_client(&client);
+        Ok(())
+    }
+
+    /// Releases the held funds of a voided or expired authorization.
+    fn process_release(&mut self, tx: &Transaction, state: AuthState) -> ApplyOutcome {
+        let mut authorization = self.get_open_authorization(tx)?;
+        let authorized = authorization.tx.amount.unwrap();
+        let mut client = self.get_account(tx.client, authorization.tx.currency);
+        client.held -= authorized;
+        client.available += authorized;
+        authorization.state = state;
+        self.write_ahead(tx)?;
+        self.tx_db.set_authorization(&authorization);
+        self.client_db.update_client(&client);
+        Ok(())
+    }
+
+    /// Returns the transaction referenced by a dispute, resolve or chargeback.
+    fn get_referenced_tx(&mut self, tx: &Transaction) -> Result<StoredTransaction, RejectReason> {
+        let referenced_tx = match self.tx_db.get_tx(&tx.tx) {
+            Some(val) => val,
+            // open authorizations are captured, voided or expired instead, a captured one
+            // is disputed as the withdrawal it became
+            None if self.tx_db.get_authorization(&tx.tx).is_some() =>
+                return Err(RejectReason::NotDisputable),
+            None => return Err(RejectReason::UnknownTx),
         };
-        if disputed_tx.client != tx.client {
+        if referenced_tx.tx.client != tx.client {
             // only client's own transactions are disputable
-            self.tx_db.remove_dispute(&tx.tx);
-            return;
+            return Err(RejectReason::ClientMismatch);
         }
+        if tx.currency.is_some() && tx.currency != referenced_tx.tx.currency {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        Ok(referenced_tx)
+    }
+
+    fn process_dispute(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let stored_tx = self.get_referenced_tx(tx)?;
+        let state = stored_tx.state.transition(TxState::Disputed, self.redispute_policy)?;
+        let disputed_tx = stored_tx.tx;
+        if !matches!(disputed_tx.tx_type,
+                TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer) {
+            return Err(RejectReason::NotDisputable.into());
+        }
+        // a dispute without an amount holds everything not charged back yet
+        let remaining = stored_tx.remaining();
+        let amount = tx.amount.unwrap_or(remaining);
+        if amount > remaining {
+            return Err(RejectReason::DisputeExceedsRemaining.into());
+        }
+        // applies in the currency of the disputed transaction
+        let mut client = self.get_account(tx.client, disputed_tx.currency);
         if disputed_tx.tx_type == TransactionType::Deposit {
-            let amount = disputed_tx.amount.unwrap();
             client.available -= amount;
             client.held += amount;
         } else if disputed_tx.tx_type == TransactionType::Withdrawal {
-            let amount = disputed_tx.amount.unwrap();
             client.held += amount;
             client.total += amount;
         } else {
-            self.tx_db.remove_dispute(&tx.tx);
-            return;
+            // the transferred funds are held by the destination
+            client = self.get_account(disputed_tx.to.unwrap(), disputed_tx.currency);
+            client.available -= amount;
+            client.held += amount;
         }
+        self.write_ahead(tx)?;
+        self.tx_db.set_state(&tx.tx, state);
+        self.tx_db.set_dispute_amounts(&tx.tx, amount, stored_tx.charged_back);
         self.client_db.update_client(&client);
+        Ok(())
     }
 
-    fn process_resolve(&mut self, tx: &Transaction) {
-        let mut client = self.client_db.get_client(tx.client);
-
-        if !self.tx_db.is_under_dispute(&tx.tx) {
-            return;
-        }
-        let disputed_tx = self.tx_db.get_tx(&tx.tx).unwrap();
+    fn process_resolve(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let stored_tx = self.get_referenced_tx(tx)?;
+        let state = stored_tx.state.transition(TxState::Resolved, self.redispute_policy)?;
+        let disputed_tx = stored_tx.tx;
+        let amount = stored_tx.disputed_amount();
+        // applies in the currency of the disputed transaction
+        let mut client = self.get_account(tx.client, disputed_tx.currency);
         if disputed_tx.tx_type == TransactionType::Deposit {
-            let amount = disputed_tx.amount.unwrap();
             client.available += amount;
             client.held -= amount;
         } else if disputed_tx.tx_type == TransactionType::Withdrawal {
-            let amount = disputed_tx.amount.unwrap();
             client.held -= amount;
             client.total -= amount;
+        } else if disputed_tx.tx_type == TransactionType::Transfer {
+            client = self.get_account(disputed_tx.to.unwrap(), disputed_tx.currency);
+            client.available += amount;
+            client.held -= amount;
         }
-        self.tx_db.remove_dispute(&tx.tx);
+        self.write_ahead(tx)?;
+        self.tx_db.set_state(&tx.tx, state);
+        self.tx_db.set_dispute_amounts(&tx.tx, Decimal::ZERO, stored_tx.charged_back);
         self.client_db.update_client(&client);
+        Ok(())
     }
 
-    fn process_chargeback(&mut self, tx: &Transaction) {
-        let mut client = self.client_db.get_client(tx.client);
-
-        if !self.tx_db.is_under_dispute(&tx.tx) {
-            return;
-        }
-        let disputed_tx = self.tx_db.get_tx(&tx.tx).unwrap();
+    fn process_chargeback(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let stored_tx = self.get_referenced_tx(tx)?;
+        let amount = stored_tx.disputed_amount();
+        let charged_back = stored_tx.charged_back + amount;
+        // the rest of a partially charged back transaction can be disputed again
+        let to = if charged_back < stored_tx.tx.amount.unwrap_or_default() {
+            TxState::PartiallyChargedBack
+        } else {
+            TxState::ChargedBack
+        };
+        let state = stored_tx.state.transition(to, self.redispute_policy)?;
+        let disputed_tx = stored_tx.tx;
+        // applies in the currency of the disputed transaction
+        let mut client = self.get_account(tx.client, disputed_tx.currency);
+        // the client the funds are taken back from is locked
+        let mut charged_client = tx.client;
+        let mut destination = None;
         if disputed_tx.tx_type == TransactionType::Deposit {
-            let amount = disputed_tx.amount.unwrap();
             client.held -= amount;
             client.total -= amount;
             client.locked = true;
         } else if disputed_tx.tx_type == TransactionType::Withdrawal {
-            let amount = disputed_tx.amount.unwrap();
             client.held -= amount;
             client.available += amount;
             client.locked = true;
+        } else if disputed_tx.tx_type == TransactionType::Transfer {
+            // the funds held by the destination go back to the client
+            client.available += amount;
+            client.total += amount;
+            charged_client = disputed_tx.to.unwrap();
+            let mut charged = self.get_account(charged_client, disputed_tx.currency);
+            charged.held -= amount;
+            charged.total -= amount;
+            charged.locked = true;
+            destination = Some(charged);
         }
-        self.tx_db.remove_dispute(&tx.tx);
-        self.tx_db.remove_tx(&tx.tx);
+        // the fee is refunded once the whole transaction is charged back
+        if to == TxState::ChargedBack {
+            client.available += stored_tx.fee;
+            client.total += stored_tx.fee;
+            client.fees -= stored_tx.fee;
+        }
+        // the charged back transaction stays in the database, its id can not be reused
+        self.write_ahead(tx)?;
+        self.tx_db.set_state(&tx.tx, state);
+        self.tx_db.set_dispute_amounts(&tx.tx, Decimal::ZERO, charged_back);
         self.client_db.update_client(&client);
+        if let Some(destination) = destination {
+            self.client_db.update_client(&destination);
+        }
+        for mut account in self.client_db.client_accounts(charged_client) {
+            if !account.locked {
+                account.locked = true;
+                self.client_db.update_client(&account);
+            }
+        }
+        Ok(())
     }
 
-    pub fn process_transaction(&mut self, tx: &Transaction) {
+    /// Applies the transaction, nothing but the account of a new client is stored if it
+    /// is rejected. Panics if the write-ahead log or the stores fail, see
+    /// `try_process_transaction`.
+    pub fn process_transaction(&mut self, tx: &Transaction) -> ProcessOutcome {
+        match self.try_process_transaction(tx) {
+            Ok(outcome) => outcome,
+            Err(err) => panic!("payments engine: {}", err),
+        }
+    }
+
+    /// Like `process_transaction`, but a failure of the write-ahead log or of committing
+    /// to the stores is returned after rolling the transaction back.
+    pub fn try_process_transaction(&mut self, tx: &Transaction) -> Result<ProcessOutcome, EngineError> {
+        self.logged_seq = None;
+        self.tx_db.begin().map_err(EngineError::Storage)?;
+        let outcome = match panic::catch_unwind(AssertUnwindSafe(|| self.apply_transaction(tx))) {
+            Ok(outcome) => outcome,
+            Err(payload) => {
+                let _ = self.tx_db.rollback();
+                panic::resume_unwind(payload);
+            },
+        };
+        let reason = match outcome {
+            Ok(()) => return match self.tx_db.commit() {
+                Ok(()) => {
+                    if let Some(seq) = self.logged_seq.take() {
+                        self.wal_seq = seq;
+                    }
+                    Ok(Ok(()))
+                },
+                Err(err) => {
+                    let _ = self.tx_db.rollback();
+                    Err(EngineError::Storage(err))
+                },
+            },
+            Err(ApplyError::Wal(err)) => {
+                let _ = self.tx_db.rollback();
+                return Err(EngineError::Wal(err));
+            },
+            Err(ApplyError::Reject(reason)) => reason,
+        };
+        self.tx_db.rollback().map_err(EngineError::Storage)?;
+        if self.client_db.client_accounts(tx.client).is_empty() {
+            // every client seen in the input is reported, even if its transaction is rejected
+            self.tx_db.begin().map_err(EngineError::Storage)?;
+            self.client_db.get_client(tx.client, tx.currency);
+            self.tx_db.commit().map_err(EngineError::Storage)?;
+        }
+        Ok(Err(reason))
+    }
+
+    fn apply_transaction(&mut self, tx: &Transaction) -> ApplyOutcome {
+        let accounts = self.client_db.client_accounts(tx.client);
+        if accounts.iter().any(|client| client.locked) && !self.lock_policy.allows(tx.tx_type) {
+            return Err(RejectReason::AccountLocked.into());
+        }
         match tx.tx_type {
             TransactionType::Deposit => self.process_deposit(tx),
             TransactionType::Withdrawal => self.process_withdrawal(tx),
             TransactionType::Dispute => self.process_dispute(tx),
             TransactionType::Resolve => self.process_resolve(tx),
             TransactionType::Chargeback => self.process_chargeback(tx),
+            TransactionType::Transfer => self.process_transfer(tx),
+            TransactionType::Limit => self.process_limit(tx),
+            TransactionType::Authorize => self.process_authorize(tx),
+            TransactionType::Capture => self.process_capture(tx),
+            TransactionType::Void => self.process_release(tx, AuthState::Voided),
+            TransactionType::Expire => self.process_release(tx, AuthState::Expired),
         }
     }
 }
@@ -137,8 +685,12 @@ impl PaymentsEngine {
 mod tests {
     use rust_decimal::Decimal;
     use crate::client::Client;
+    use crate::outcome::{ProcessOutcome, RejectReason};
     use crate::tx::{Transaction, TransactionType};
-    use crate::payments_engine::{PaymentsEngine, get_payments_engine};
+    use crate::payments_engine::{PaymentsEngine, LockPolicy};
+    use crate::store::{ClientStore, TransactionStore};
+    use crate::tx_database::{TxState, RedisputePolicy};
+    use crate::snapshot::Snapshot;
 
     fn perform_deposit_1(engine: &mut PaymentsEngine) {
         let tx = Transaction{
@@ -146,8 +698,10 @@ mod tests {
             client: 1,
             tx: 1,
             amount: Some(Decimal::from(1)),
+            currency: None,
+            to: None,
         };
-        engine.process_deposit(&tx);
+        engine.process_deposit(&tx).unwrap();
     }
 
     fn perform_withdrawal_2(engine: &mut PaymentsEngine) {
@@ -156,8 +710,10 @@ mod tests {
             client: 1,
             tx: 2,
             amount: Some(Decimal::from(2)),
+            currency: None,
+            to: None,
         };
-        engine.process_withdrawal(&tx);
+        engine.process_withdrawal(&tx).unwrap();
     }
 
     fn perform_dispute_tx1(engine: &mut PaymentsEngine) {
@@ -166,8 +722,10 @@ mod tests {
             client: 1,
             tx: 1,
             amount: None,
+            currency: None,
+            to: None,
         };
-        engine.process_dispute(&tx3);
+        engine.process_dispute(&tx3).unwrap();
     }
 
     fn perform_dispute_tx2(engine: &mut PaymentsEngine) {
@@ -176,8 +734,10 @@ mod tests {
             client: 1,
             tx: 2,
             amount: None,
+            currency: None,
+            to: None,
         };
-        engine.process_dispute(&tx3);
+        engine.process_dispute(&tx3).unwrap();
     }
 
     fn perform_resolve_tx1(engine: &mut PaymentsEngine) {
@@ -186,8 +746,10 @@ mod tests {
             client: 1,
             tx: 1,
             amount: None,
+            currency: None,
+            to: None,
         };
-        engine.process_resolve(&tx4);
+        engine.process_resolve(&tx4).unwrap();
     }
 
     fn perform_resolve_tx2(engine: &mut PaymentsEngine) {
@@ -196,8 +758,10 @@ mod tests {
             client: 1,
             tx: 2,
             amount: None,
+            currency: None,
+            to: None,
         };
-        engine.process_resolve(&tx4);
+        engine.process_resolve(&tx4).unwrap();
     }
 
     fn perform_chargeback_tx1(engine: &mut PaymentsEngine) {
@@ -206,8 +770,10 @@ mod tests {
             client: 1,
             tx: 1,
             amount: None,
+            currency: None,
+            to: None,
         };
-        engine.process_chargeback(&tx4);
+        engine.process_chargeback(&tx4).unwrap();
     }
 
     fn perform_chargeback_tx2(engine: &mut PaymentsEngine) {
@@ -216,18 +782,34 @@ mod tests {
             client: 1,
             tx: 2,
             amount: None,
+            currency: None,
+            to: None,
         };
-        engine.process_chargeback(&tx4);
+        engine.process_chargeback(&tx4).unwrap();
+    }
+
+    /// Transaction of the client in the default currency, amounts are whole units.
+    fn tx(tx_type: TransactionType, client: u16, tx: u32, amount: Option<i64>) -> Transaction {
+        Transaction{tx_type, client, tx, amount: amount.map(Decimal::from), currency: None, to: None}
+    }
+
+    /// Available, held and total funds of the client in the default currency.
+    fn balances(engine: &PaymentsEngine, client_id: u16) -> [Decimal; 3] {
+        let client = engine.client(client_id).unwrap();
+        [client.available, client.held, client.total]
     }
 
     fn get_test_payments_engine_10() -> PaymentsEngine {
-        let mut engine = get_payments_engine();
+        let mut engine = PaymentsEngine::new();
         let client = Client{
             id: 1,
+            currency: None,
             available: Decimal::from(10),
             held: Decimal::from(0),
             total: Decimal::from(10),
+            fees: Decimal::ZERO,
             locked: false,
+            credit_limit: Decimal::ZERO,
         };
         engine.client_db.update_client(&client);
         engine
@@ -238,13 +820,16 @@ mod tests {
         let mut engine = get_test_payments_engine_10();
         perform_deposit_1(&mut engine);
         let client_id: u16 = 1;
-        let updated_client = engine.client_db.get_client(client_id);
+        let updated_client = engine.client(client_id).unwrap();
         let expected_client = Client {
             id: 1,
+            currency: None,
             available: Decimal::from(11),
             held: Decimal::from(0),
             total: Decimal::from(11),
+            fees: Decimal::ZERO,
             locked: false,
+            credit_limit: Decimal::ZERO,
         };
         assert_eq!(updated_client, expected_client);
     }
@@ -254,13 +839,16 @@ mod tests {
         let mut engine = get_test_payments_engine_10();
         perform_withdrawal_2(&mut engine);
         let client_id: u16 = 1;
-        let updated_client = engine.client_db.get_client(client_id);
+        let updated_client = engine.client(client_id).unwrap();
         let expected_client = Client {
             id: 1,
+            currency: None,
             available: Decimal::from(8),
             held: Decimal::from(0),
             total: Decimal::from(8),
+            fees: Decimal::ZERO,
             locked: false,
+            credit_limit: Decimal::ZERO,
         };
         assert_eq!(updated_client, expected_client);
     }
@@ -272,13 +860,16 @@ mod tests {
         perform_withdrawal_2(&mut engine);
         perform_dispute_tx1(&mut engine);
         let client_id: u16 = 1;
-        let updated_client = engine.client_db.get_client(client_id);
+        let updated_client = engine.client(client_id).unwrap();
         let expected_client = Client {
             id: 1,
+            currency: None,
             available: Decimal::from(8),
             held: Decimal::from(1),
             total: Decimal::from(9),
+            fees: Decimal::ZERO,
             locked: false,
+            credit_limit: Decimal::ZERO,
         };
         assert_eq!(updated_client, expected_client);
     }
@@ -290,13 +881,16 @@ mod tests {
         perform_withdrawal_2(&mut engine);
         perform_dispute_tx2(&mut engine);
         let client_id: u16 = 1;
-        let updated_client = engine.client_db.get_client(client_id);
+        let updated_client = engine.client(client_id).unwrap();
         let expected_client = Client {
             id: 1,
+            currency: None,
             available: Decimal::from(9),
             held: Decimal::from(2),
             total: Decimal::from(11),
+            fees: Decimal::ZERO,
             locked: false,
+            credit_limit: Decimal::ZERO,
         };
         assert_eq!(updated_client, expected_client);
     }
@@ -309,13 +903,16 @@ mod tests {
         perform_dispute_tx1(&mut engine);
         perform_resolve_tx1(&mut engine);
         let client_id: u16 = 1;
-        let updated_client = engine.client_db.get_client(client_id);
+        let updated_client = engine.client(client_id).unwrap();
         let expected_client = Client {
             id: 1,
+            currency: None,
             available: Decimal::from(9),
             held: Decimal::from(0),
             total: Decimal::from(9),
+            fees: Decimal::ZERO,
             locked: false,
+            credit_limit: Decimal::ZERO,
         };
         assert_eq!(updated_client, expected_client);
     }
@@ -328,13 +925,16 @@ mod tests {
         perform_dispute_tx2(&mut engine);
         perform_resolve_tx2(&mut engine);
         let client_id: u16 = 1;
-        let updated_client = engine.client_db.get_client(client_id);
+        let updated_client = engine.client(client_id).unwrap();
         let expected_client = Client {
             id: 1,
+            currency: None,
             available: Decimal::from(9),
             held: Decimal::from(0),
             total: Decimal::from(9),
+            fees: Decimal::ZERO,
             locked: false,
+            credit_limit: Decimal::ZERO,
         };
         assert_eq!(updated_client, expected_client);
     }
@@ -347,13 +947,16 @@ mod tests {
         perform_dispute_tx1(&mut engine);
         perform_chargeback_tx1(&mut engine);
         let client_id: u16 = 1;
-        let updated_client = engine.client_db.get_client(client_id);
+        let updated_client = engine.client(client_id).unwrap();
         let expected_client = Client {
             id: 1,
+            currency: None,
             available: Decimal::from(8),
             held: Decimal::from(0),
             total: Decimal::from(8),
+            fees: Decimal::ZERO,
             locked: true,
+            credit_limit: Decimal::ZERO,
         };
         assert_eq!(updated_client, expected_client);
     }
@@ -366,15 +969,522 @@ mod tests {
         perform_dispute_tx2(&mut engine);
         perform_chargeback_tx2(&mut engine);
         let client_id: u16 = 1;
-        let updated_client = engine.client_db.get_client(client_id);
+        let updated_client = engine.client(client_id).unwrap();
         let expected_client = Client {
             id: 1,
+            currency: None,
             available: Decimal::from(11),
             held: Decimal::from(0),
             total: Decimal::from(11),
+            fees: Decimal::ZERO,
             locked: true,
+            credit_limit: Decimal::ZERO,
         };
         assert_eq!(updated_client, expected_client);
     }
-}
 
+    fn perform_deposit_3(engine: &mut PaymentsEngine) -> ProcessOutcome {
+        let tx = Transaction{
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 3,
+            amount: Some(Decimal::from(3)),
+            currency: None,
+            to: None,
+        };
+        engine.process_transaction(&tx)
+    }
+
+    fn perform_withdrawal_4(engine: &mut PaymentsEngine) -> ProcessOutcome {
+        let tx = Transaction{
+            tx_type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 4,
+            amount: Some(Decimal::from(1)),
+            currency: None,
+            to: None,
+        };
+        engine.process_transaction(&tx)
+    }
+
+    fn get_test_payments_engine_locked(lock_policy: LockPolicy) -> PaymentsEngine {
+        let mut engine = get_test_payments_engine_10();
+        engine.set_lock_policy(lock_policy);
+        perform_deposit_1(&mut engine);
+        perform_withdrawal_2(&mut engine);
+        perform_dispute_tx2(&mut engine);
+        perform_dispute_tx1(&mut engine);
+        perform_chargeback_tx1(&mut engine);
+        engine
+    }
+
+    #[test]
+    fn locked_reject_all_functionality() {
+        let mut engine = get_test_payments_engine_locked(LockPolicy::RejectAll);
+        let locked_client = engine.client(1).unwrap();
+        assert_eq!(perform_deposit_3(&mut engine), Err(RejectReason::AccountLocked));
+        assert_eq!(perform_withdrawal_4(&mut engine), Err(RejectReason::AccountLocked));
+        let tx = Transaction{
+            tx_type: TransactionType::Resolve,
+            client: 1,
+            tx: 2,
+            amount: None,
+            currency: None,
+            to: None,
+        };
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::AccountLocked));
+        assert_eq!(engine.client(1).unwrap(), locked_client);
+    }
+
+    #[test]
+    fn locked_allow_deposits_functionality() {
+        let mut engine = get_test_payments_engine_locked(LockPolicy::AllowDeposits);
+        assert_eq!(perform_deposit_3(&mut engine), Ok(()));
+        assert_eq!(perform_withdrawal_4(&mut engine), Err(RejectReason::AccountLocked));
+        let updated_client = engine.client(1).unwrap();
+        let expected_client = Client {
+            id: 1,
+            currency: None,
+            available: Decimal::from(11),
+            held: Decimal::from(2),
+            total: Decimal::from(13),
+            fees: Decimal::ZERO,
+            locked: true,
+            credit_limit: Decimal::ZERO,
+        };
+        assert_eq!(updated_client, expected_client);
+    }
+
+    #[test]
+    fn locked_allow_disputes_functionality() {
+        let mut engine = get_test_payments_engine_locked(LockPolicy::AllowDisputes);
+        assert_eq!(perform_deposit_3(&mut engine), Err(RejectReason::AccountLocked));
+        assert_eq!(perform_withdrawal_4(&mut engine), Err(RejectReason::AccountLocked));
+        let tx = Transaction{
+            tx_type: TransactionType::Resolve,
+            client: 1,
+            tx: 2,
+            amount: None,
+            currency: None,
+            to: None,
+        };
+        assert_eq!(engine.process_transaction(&tx), Ok(()));
+        let updated_client = engine.client(1).unwrap();
+        let expected_client = Client {
+            id: 1,
+            currency: None,
+            available: Decimal::from(8),
+            held: Decimal::from(0),
+            total: Decimal::from(8),
+            fees: Decimal::ZERO,
+            locked: true,
+            credit_limit: Decimal::ZERO,
+        };
+        assert_eq!(updated_client, expected_client);
+    }
+
+    #[test]
+    fn reject_reasons_functionality() {
+        let mut engine = get_test_payments_engine_10();
+        let mut tx = Transaction{
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::from(1)),
+            currency: None,
+            to: None,
+        };
+        assert_eq!(engine.process_transaction(&tx), Ok(()));
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::DuplicateTxId));
+        tx.tx_type = TransactionType::Withdrawal;
+        tx.tx = 2;
+        tx.amount = Some(Decimal::from(12));
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::InsufficientFunds));
+        tx.tx_type = TransactionType::Dispute;
+        tx.amount = None;
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::UnknownTx));
+        tx.tx = 1;
+        tx.client = 2;
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::ClientMismatch));
+        tx.client = 1;
+        tx.tx_type = TransactionType::Resolve;
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::NotUnderDispute));
+        tx.tx_type = TransactionType::Dispute;
+        assert_eq!(engine.process_transaction(&tx), Ok(()));
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::AlreadyDisputed));
+    }
+
+    #[test]
+    fn unvalidated_transaction_functionality() {
+        let mut engine = get_test_payments_engine_10();
+        for tx_type in [TransactionType::Deposit, TransactionType::Withdrawal, TransactionType::Transfer,
+                TransactionType::Limit, TransactionType::Authorize] {
+            assert_eq!(engine.process_transaction(&tx(tx_type, 1, 1, None)), Err(RejectReason::MissingAmount));
+        }
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Transfer, 1, 1, Some(1))),
+            Err(RejectReason::MissingDestination));
+        assert_eq!(balances(&engine, 1), [10, 0, 10].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(1))), Ok(()));
+    }
+
+    #[test]
+    fn chargeback_keeps_history_functionality() {
+        let mut engine = get_test_payments_engine_10();
+        perform_deposit_1(&mut engine);
+        perform_dispute_tx1(&mut engine);
+        perform_chargeback_tx1(&mut engine);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().state, TxState::ChargedBack);
+        engine.set_lock_policy(LockPolicy::AllowDeposits);
+        let tx = Transaction{
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::from(1)),
+            currency: None,
+            to: None,
+        };
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::DuplicateTxId));
+        engine.set_lock_policy(LockPolicy::AllowDisputes);
+        let tx = Transaction{
+            tx_type: TransactionType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            currency: None,
+            to: None,
+        };
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::AlreadyChargedBack));
+    }
+
+    #[test]
+    fn redispute_policy_functionality() {
+        let mut engine = get_test_payments_engine_10();
+        perform_deposit_1(&mut engine);
+        perform_dispute_tx1(&mut engine);
+        perform_resolve_tx1(&mut engine);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().state, TxState::Resolved);
+        let tx = Transaction{
+            tx_type: TransactionType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            currency: None,
+            to: None,
+        };
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::AlreadyDisputed));
+        engine.set_redispute_policy(RedisputePolicy::AfterResolve);
+        assert_eq!(engine.process_transaction(&tx), Ok(()));
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().state, TxState::Disputed);
+    }
+
+    #[test]
+    fn wal_recovery_functionality() {
+        use std::env;
+        use std::fs;
+
+        let wal_path = env::temp_dir().join("toy_payments_engine_recovery.wal");
+        let snapshot_path = env::temp_dir().join("toy_payments_engine_recovery.json");
+        let _ = fs::remove_file(&wal_path);
+        let expected_snapshot;
+        {
+            let mut engine = get_test_payments_engine_10();
+            assert_eq!(engine.recover_from_wal(&wal_path).unwrap(), 0);
+            assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(1))), Ok(()));
+            engine.checkpoint(&snapshot_path).unwrap();
+            assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 1, 2, Some(11))), Ok(()));
+            assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 2, None)), Ok(()));
+            // rejected transactions are not logged
+            assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 1, 5, Some(100))),
+                Err(RejectReason::InsufficientFunds));
+            assert_eq!(engine.wal_seq(), 3);
+            expected_snapshot = engine.snapshot();
+        }
+        // without the snapshot the logged withdrawal is rejected
+        let mut engine = PaymentsEngine::new();
+        assert!(engine.recover_from_wal(&wal_path).is_err());
+
+        let mut engine = PaymentsEngine::new();
+        engine.restore(&Snapshot::load(&snapshot_path).unwrap()).unwrap();
+        assert_eq!(engine.recover_from_wal(&wal_path).unwrap(), 2);
+        assert_eq!(engine.snapshot(), expected_snapshot);
+        fs::remove_file(&wal_path).unwrap();
+        fs::remove_file(&snapshot_path).unwrap();
+    }
+
+    #[test]
+    fn multi_currency_functionality() {
+        let eur = Some("EUR".parse().unwrap());
+        let usd = Some("USD".parse().unwrap());
+        let mut engine = PaymentsEngine::new();
+        let tx = |tx_type, tx_id, amount, currency| Transaction{currency, ..tx(tx_type, 1, tx_id, amount)};
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, Some(10), eur)), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 2, Some(3), usd)), Ok(()));
+        // balances are separate per currency
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 3, Some(5), usd)),
+            Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 3, Some(5), eur)), Ok(()));
+        assert_eq!(engine.client(1), None);
+        assert_eq!(engine.account(1, eur).unwrap().available, Decimal::from(5));
+        assert_eq!(engine.account(1, usd).unwrap().available, Decimal::from(3));
+
+        // disputes apply in the currency of the disputed transaction
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 2, None, eur)),
+            Err(RejectReason::CurrencyMismatch));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 2, None, None)), Ok(()));
+        assert_eq!(engine.account(1, usd).unwrap().held, Decimal::from(3));
+        assert_eq!(engine.account(1, eur).unwrap().held, Decimal::from(0));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Chargeback, 2, None, usd)), Ok(()));
+        assert_eq!(engine.client(1), None);
+
+        // a chargeback locks all accounts of the client, also the ones opened later
+        assert!(engine.account(1, eur).unwrap().locked);
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 4, Some(1), eur)),
+            Err(RejectReason::AccountLocked));
+        engine.set_lock_policy(LockPolicy::AllowDeposits);
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 5, Some(1), None)), Ok(()));
+        assert!(engine.client(1).unwrap().locked);
+        assert_eq!(engine.client_db().clients().len(), 3);
+    }
+
+    #[test]
+    fn fees_functionality() {
+        use crate::fees::{FeeRule, FeeSchedule};
+
+        let mut engine = PaymentsEngine::new();
+        engine.set_fee_schedule(FeeSchedule{
+            deposit: Some(FeeRule{percent: Decimal::ONE, ..FeeRule::default()}),
+            withdrawal: Some(FeeRule{flat: Decimal::new(5, 1), ..FeeRule::default()}),
+        });
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(100))), Ok(()));
+        assert_eq!(engine.client(1).unwrap().available, Decimal::from(99));
+        // the fee counts towards the funds needed
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 1, 2, Some(99))),
+            Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.client(1).unwrap().fees, Decimal::ONE);
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 1, 2, Some(90))), Ok(()));
+        assert_eq!(balances(&engine, 1), [Decimal::new(85, 1), Decimal::ZERO, Decimal::new(85, 1)]);
+        assert_eq!(engine.client(1).unwrap().fees, Decimal::new(15, 1));
+        assert_eq!(engine.tx_db().get_tx(&2).unwrap().fee, Decimal::new(5, 1));
+
+        // a chargeback refunds the fee of the transaction
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 2, None)), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Chargeback, 1, 2, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [99, 0, 99].map(Decimal::from));
+        assert_eq!(engine.client(1).unwrap().fees, Decimal::ONE);
+
+        // a deposit fee above the amount takes the whole amount only
+        engine.set_fee_schedule(FeeSchedule{
+            deposit: Some(FeeRule{flat: Decimal::from(2), ..FeeRule::default()}),
+            ..FeeSchedule::default()
+        });
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 2, 3, Some(1))), Ok(()));
+        assert_eq!(balances(&engine, 2), [Decimal::ZERO; 3]);
+        assert_eq!(engine.client(2).unwrap().fees, Decimal::ONE);
+    }
+
+    #[test]
+    fn transfer_functionality() {
+        let mut engine = PaymentsEngine::new();
+        let transfer = |client, tx_id, amount, to| Transaction{
+            to: Some(to),
+            ..tx(TransactionType::Transfer, client, tx_id, Some(amount))
+        };
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(10))), Ok(()));
+        assert_eq!(engine.process_transaction(&transfer(1, 2, 11, 2)), Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.process_transaction(&transfer(1, 2, 4, 2)), Ok(()));
+        assert_eq!(balances(&engine, 1), [6, 0, 6].map(Decimal::from));
+        assert_eq!(balances(&engine, 2), [4, 0, 4].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&transfer(1, 1, 1, 2)), Err(RejectReason::DuplicateTxId));
+        assert_eq!(engine.process_transaction(&transfer(1, 5, 1, 1)), Err(RejectReason::SelfTransfer));
+        assert_eq!(balances(&engine, 1), [6, 0, 6].map(Decimal::from));
+
+        // only the source client disputes a transfer, the destination holds the funds
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 2, 2, None)),
+            Err(RejectReason::ClientMismatch));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 2, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [6, 0, 6].map(Decimal::from));
+        assert_eq!(balances(&engine, 2), [0, 4, 4].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Resolve, 1, 2, None)), Ok(()));
+        assert_eq!(balances(&engine, 2), [4, 0, 4].map(Decimal::from));
+
+        // a chargeback moves the funds back and locks the destination
+        engine.set_redispute_policy(RedisputePolicy::AfterResolve);
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 2, None)), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Chargeback, 1, 2, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [10, 0, 10].map(Decimal::from));
+        assert_eq!(balances(&engine, 2), [0, 0, 0].map(Decimal::from));
+        assert!(!engine.client(1).unwrap().locked);
+        assert!(engine.client(2).unwrap().locked);
+        assert_eq!(engine.process_transaction(&transfer(1, 3, 1, 2)), Err(RejectReason::AccountLocked));
+        assert_eq!(engine.process_transaction(&transfer(2, 4, 1, 1)), Err(RejectReason::AccountLocked));
+        engine.set_lock_policy(LockPolicy::AllowDeposits);
+        assert_eq!(engine.process_transaction(&transfer(1, 3, 1, 2)), Ok(()));
+        assert_eq!(balances(&engine, 1), [9, 0, 9].map(Decimal::from));
+        assert_eq!(balances(&engine, 2), [1, 0, 1].map(Decimal::from));
+    }
+
+    #[test]
+    fn credit_limit_functionality() {
+        use crate::fees::{FeeRule, FeeSchedule};
+        use crate::profiles::ClientProfile;
+
+        let mut engine = PaymentsEngine::new();
+        engine.apply_profiles(&[ClientProfile{client: 1, currency: None, credit_limit: Decimal::from(5)}]).unwrap();
+        engine.set_fee_schedule(FeeSchedule{
+            withdrawal: Some(FeeRule{flat: Decimal::ONE, ..FeeRule::default()}),
+            ..FeeSchedule::default()
+        });
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(2))), Ok(()));
+        // the fee counts against the limit too
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 1, 2, Some(7))),
+            Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 1, 2, Some(4))), Ok(()));
+        assert_eq!(engine.client(1).unwrap().available, Decimal::from(-3));
+        let transfer = |tx_id, amount| Transaction{to: Some(2), ..tx(TransactionType::Transfer, 1, tx_id, Some(amount))};
+        assert_eq!(engine.process_transaction(&transfer(3, 3)), Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.process_transaction(&transfer(3, 2)), Ok(()));
+        assert_eq!(engine.client(1).unwrap().available, Decimal::from(-5));
+
+        // the admin transaction changes the limit, also of a locked client
+        engine.client_db.update_client(&Client{locked: true, ..engine.client(2).unwrap()});
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Limit, 2, 4, Some(1))), Ok(()));
+        assert_eq!(engine.client(2).unwrap().credit_limit, Decimal::ONE);
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Limit, 1, 5, Some(0))), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 5, None)),
+            Err(RejectReason::NotDisputable));
+        let client = engine.client(1).unwrap();
+        assert_eq!((client.available, client.credit_limit), (Decimal::from(-5), Decimal::ZERO));
+        // a deposit below the lowered limit still pays the overdraft back
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 6, Some(1))), Ok(()));
+        assert_eq!(engine.client(1).unwrap().available, Decimal::from(-4));
+    }
+
+    #[test]
+    fn negative_account_deposit_functionality() {
+        let mut engine = PaymentsEngine::new();
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(10))), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Withdrawal, 1, 2, Some(10))), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 1, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [-10, 10, 0].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 3, Some(5))), Ok(()));
+        assert_eq!(balances(&engine, 1), [-5, 10, 5].map(Decimal::from));
+    }
+
+    #[test]
+    fn authorization_functionality() {
+        use crate::tx_database::AuthState;
+
+        let mut engine = PaymentsEngine::new();
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(10))), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Authorize, 1, 2, Some(11))),
+            Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Authorize, 1, 2, Some(6))), Ok(()));
+        assert_eq!(balances(&engine, 1), [4, 6, 10].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Authorize, 1, 1, Some(1))),
+            Err(RejectReason::DuplicateTxId));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 2, Some(1))),
+            Err(RejectReason::DuplicateTxId));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 2, None)),
+            Err(RejectReason::NotDisputable));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Capture, 1, 1, None)),
+            Err(RejectReason::UnknownTx));
+
+        // a partial capture releases the rest of the hold
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Capture, 1, 2, Some(7))),
+            Err(RejectReason::CaptureExceedsAuthorization));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Capture, 1, 2, Some(4))), Ok(()));
+        assert_eq!(balances(&engine, 1), [6, 0, 6].map(Decimal::from));
+        assert_eq!(engine.tx_db().get_authorization(&2).unwrap().captured, Decimal::from(4));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Void, 1, 2, None)),
+            Err(RejectReason::AuthorizationClosed));
+
+        // the capture is disputed as a withdrawal of the captured amount
+        let stored_tx = engine.tx_db().get_tx(&2).unwrap();
+        assert_eq!((stored_tx.tx.tx_type, stored_tx.tx.amount), (TransactionType::Withdrawal, Some(Decimal::from(4))));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 2, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [6, 4, 10].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Resolve, 1, 2, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [6, 0, 6].map(Decimal::from));
+
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Authorize, 1, 3, Some(5))), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Authorize, 1, 4, Some(1))), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Capture, 1, 3, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [0, 1, 1].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Expire, 1, 4, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [1, 0, 1].map(Decimal::from));
+        assert_eq!(engine.tx_db().get_authorization(&4).unwrap().state, AuthState::Expired);
+    }
+
+    #[test]
+    fn capture_fee_functionality() {
+        use crate::fees::{FeeRule, FeeSchedule};
+
+        let mut engine = PaymentsEngine::new();
+        engine.set_fee_schedule(FeeSchedule{
+            withdrawal: Some(FeeRule{flat: Decimal::ONE, ..FeeRule::default()}),
+            ..FeeSchedule::default()
+        });
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(5))), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Authorize, 1, 2, Some(5))), Ok(()));
+        // nothing is left for the fee of a full capture
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Capture, 1, 2, None)),
+            Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Capture, 1, 2, Some(3))), Ok(()));
+        assert_eq!(balances(&engine, 1), [1, 0, 1].map(Decimal::from));
+        assert_eq!(engine.client(1).unwrap().fees, Decimal::ONE);
+        assert_eq!(engine.tx_db().get_tx(&2).unwrap().fee, Decimal::ONE);
+
+        // a chargeback of the capture returns the captured amount and the fee
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 2, None)), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Chargeback, 1, 2, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [5, 0, 5].map(Decimal::from));
+        assert!(engine.client(1).unwrap().locked);
+    }
+
+    #[test]
+    fn partial_disputes_functionality() {
+        use crate::fees::{FeeRule, FeeSchedule};
+
+        let mut engine = PaymentsEngine::new();
+        engine.set_lock_policy(LockPolicy::AllowDisputes);
+        engine.set_fee_schedule(FeeSchedule{
+            deposit: Some(FeeRule{flat: Decimal::ONE, ..FeeRule::default()}),
+            withdrawal: None,
+        });
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Deposit, 1, 1, Some(11))), Ok(()));
+        assert_eq!(balances(&engine, 1), [10, 0, 10].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 1, Some(12))),
+            Err(RejectReason::DisputeExceedsRemaining));
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().state, TxState::Processed);
+
+        // a resolve releases only the disputed part
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 1, Some(4))), Ok(()));
+        assert_eq!(balances(&engine, 1), [6, 4, 10].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Resolve, 1, 1, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [10, 0, 10].map(Decimal::from));
+
+        // a partial chargeback keeps the fee and leaves the rest disputable
+        engine.set_redispute_policy(RedisputePolicy::AfterResolve);
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 1, Some(3))), Ok(()));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Chargeback, 1, 1, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [7, 0, 7].map(Decimal::from));
+        assert_eq!(engine.client(1).unwrap().fees, Decimal::ONE);
+        assert!(engine.client(1).unwrap().locked);
+        let stored_tx = engine.tx_db().get_tx(&1).unwrap();
+        assert_eq!((stored_tx.state, stored_tx.charged_back, stored_tx.remaining()),
+            (TxState::PartiallyChargedBack, Decimal::from(3), Decimal::from(8)));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 1, Some(9))),
+            Err(RejectReason::DisputeExceedsRemaining));
+        assert_eq!(balances(&engine, 1), [7, 0, 7].map(Decimal::from));
+
+        // the rest is disputed without an amount and charged back with the fee
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 1, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [-1, 8, 7].map(Decimal::from));
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Chargeback, 1, 1, None)), Ok(()));
+        assert_eq!(balances(&engine, 1), [0, 0, 0].map(Decimal::from));
+        assert_eq!(engine.client(1).unwrap().fees, Decimal::ZERO);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().state, TxState::ChargedBack);
+        assert_eq!(engine.process_transaction(&tx(TransactionType::Dispute, 1, 1, Some(1))),
+            Err(RejectReason::AlreadyChargedBack));
+    }
+}
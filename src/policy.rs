@@ -0,0 +1,129 @@
+use std::fmt;
+use std::io;
+
+use clap::Args;
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+
+use crate::fees::FeeSchedule;
+use crate::outcome::EngineError;
+use crate::payments_engine::{PaymentsEngine, LockPolicy};
+use crate::profiles::{ClientProfile, load_profiles};
+use crate::store::{ClientStore, TransactionStore};
+use crate::tx_database::RedisputePolicy;
+
+/// Command line options configuring the policies, fees and credit limits of an engine,
+/// shared by the binaries.
+#[derive(Debug, Args)]
+pub struct PolicyArgs {
+    /// Transactions still accepted for locked clients.
+    #[arg(long, value_name = "POLICY", default_value = "reject-all",
+        value_parser = PossibleValuesParser::new(["reject-all", "allow-deposits", "allow-disputes"])
+            .map(|s| s.parse::<LockPolicy>().unwrap()))]
+    pub lock_policy: LockPolicy,
+
+    /// Whether a resolved transaction can be disputed again.
+    #[arg(long, value_name = "POLICY", default_value = "never",
+        value_parser = PossibleValuesParser::new(["never", "after-resolve"])
+            .map(|s| s.parse::<RedisputePolicy>().unwrap()))]
+    pub redispute_policy: RedisputePolicy,
+
+    /// Charge deposit and withdrawal fees from this JSON fee schedule.
+    #[arg(long, value_name = "FILE")]
+    pub fees: Option<String>,
+
+    /// Set the credit limits of the accounts from this CSV profile file.
+    #[arg(long, value_name = "FILE")]
+    pub profiles: Option<String>,
+}
+
+/// A policy option can not be applied to an engine.
+#[derive(Debug)]
+pub enum PolicyError {
+    /// The fee schedule file can not be read.
+    Fees(String, io::Error),
+    /// The profile file can not be read.
+    Profiles(String, io::Error),
+    /// The engine failed to store the credit limits of the profiles.
+    ApplyProfiles(EngineError),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::Fees(fpath, err) => write!(f, "can not read fee schedule {}: {}", fpath, err),
+            PolicyError::Profiles(fpath, err) => write!(f, "can not read profiles {}: {}", fpath, err),
+            PolicyError::ApplyProfiles(err) => write!(f, "can not apply profiles: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+impl PolicyArgs {
+    /// Loads the fee schedule, without a file no fees are charged.
+    pub fn fee_schedule(&self) -> Result<FeeSchedule, PolicyError> {
+        match &self.fees {
+            Some(fpath) => FeeSchedule::load(fpath).map_err(|err| PolicyError::Fees(fpath.clone(), err)),
+            None => Ok(FeeSchedule::default()),
+        }
+    }
+
+    /// Loads the client profiles, without a file there are none.
+    pub fn client_profiles(&self) -> Result<Vec<ClientProfile>, PolicyError> {
+        match &self.profiles {
+            Some(fpath) => load_profiles(fpath).map_err(|err| PolicyError::Profiles(fpath.clone(), err)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Sets the policies and the fee schedule of the engine. The profiles are left to the
+    /// caller, they have to be applied after a restore.
+    pub fn configure<C: ClientStore, T: TransactionStore>(&self, payments_engine: &mut PaymentsEngine<C, T>)
+            -> Result<(), PolicyError> {
+        payments_engine.set_lock_policy(self.lock_policy);
+        payments_engine.set_redispute_policy(self.redispute_policy);
+        payments_engine.set_fee_schedule(self.fee_schedule()?);
+        Ok(())
+    }
+
+    /// Builds an in-memory engine with the policies, fee schedule and client profiles.
+    pub fn build_engine(&self) -> Result<PaymentsEngine, PolicyError> {
+        let mut payments_engine = PaymentsEngine::new();
+        self.configure(&mut payments_engine)?;
+        payments_engine.apply_profiles(&self.client_profiles()?).map_err(PolicyError::ApplyProfiles)?;
+        Ok(payments_engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use clap::Parser;
+    use rust_decimal::Decimal;
+    use crate::payments_engine::LockPolicy;
+    use crate::policy::{PolicyArgs, PolicyError};
+    use crate::tx_database::RedisputePolicy;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[command(flatten)]
+        policy: PolicyArgs,
+    }
+
+    #[test]
+    fn build_engine_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_policy_profiles.csv");
+        fs::write(&fpath, "client,currency,credit_limit\n3,,50\n").unwrap();
+        let args = TestArgs::parse_from(["engine", "--lock-policy", "allow-deposits",
+            "--redispute-policy", "after-resolve", "--profiles", fpath.to_str().unwrap()]);
+        let payments_engine = args.policy.build_engine().unwrap();
+        assert_eq!(payments_engine.lock_policy(), LockPolicy::AllowDeposits);
+        assert_eq!(payments_engine.redispute_policy(), RedisputePolicy::AfterResolve);
+        assert_eq!(payments_engine.client(3).unwrap().total, Decimal::ZERO);
+        fs::remove_file(&fpath).unwrap();
+
+        let args = TestArgs::parse_from(["engine", "--fees", "missing_fees.json"]);
+        assert!(matches!(args.policy.build_engine(), Err(PolicyError::Fees(fpath, _)) if fpath == "missing_fees.json"));
+    }
+}
@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::currency::Currency;
+use crate::tx_reader::transaction_reader;
+
+/// Settings of a client account from a profile file.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Deserialize)]
+pub struct ClientProfile {
+    pub client: u16,
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    pub credit_limit: Decimal,
+}
+
+/// Loads a CSV profile file with a `client,currency,credit_limit` header row, the currency
+/// may be left empty.
+pub fn load_profiles<P: AsRef<Path>>(fpath: P) -> io::Result<Vec<ClientProfile>> {
+    let mut reader = transaction_reader(File::open(fpath)?);
+    let mut profiles = Vec::new();
+    for row in reader.deserialize::<ClientProfile>() {
+        let profile = row.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if profile.credit_limit.is_sign_negative() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("negative credit limit of client {}", profile.client)));
+        }
+        profiles.push(profile);
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use rust_decimal::Decimal;
+    use crate::profiles::load_profiles;
+
+    #[test]
+    fn load_profiles_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_profiles.csv");
+        fs::write(&fpath, "client, currency, credit_limit\n1, , 100\n1, eur, 2.5\n").unwrap();
+        let profiles = load_profiles(&fpath).unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!((profiles[0].client, profiles[0].currency, profiles[0].credit_limit),
+            (1, None, Decimal::from(100)));
+        assert_eq!(profiles[1].currency, Some("EUR".parse().unwrap()));
+
+        fs::write(&fpath, "client,currency,credit_limit\n2,,-5\n").unwrap();
+        assert!(load_profiles(&fpath).is_err());
+        fs::write(&fpath, "client,currency,credit_limit\n2,,lots\n").unwrap();
+        assert!(load_profiles(&fpath).is_err());
+        fs::remove_file(&fpath).unwrap();
+        assert!(load_profiles(&fpath).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+use std::fs::File;
+use std::str::FromStr;
+use std::io::{BufWriter, Write};
+
+use serde::Serialize;
+
+use crate::outcome::RejectReason;
+use crate::tx::ValidationError;
+
+/// Why an input row did not make it into the accounts.
+#[derive(Debug)]
+pub enum RejectCause {
+    Parse(csv::Error),
+    ParseJson(serde_json::Error),
+    Validation(ValidationError),
+    Engine(RejectReason),
+}
+
+impl RejectCause {
+    pub fn stage(&self) -> &'static str {
+        match self {
+            RejectCause::Parse(_) | RejectCause::ParseJson(_) => "parse",
+            RejectCause::Validation(_) => "validation",
+            RejectCause::Engine(_) => "engine",
+        }
+    }
+}
+
+impl fmt::Display for RejectCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectCause::Parse(err) => write!(f, "{}", err),
+            RejectCause::ParseJson(err) => write!(f, "{}", err),
+            RejectCause::Validation(err) => write!(f, "{}", err),
+            RejectCause::Engine(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RejectRow<'a> {
+    input: &'a str,
+    row: u64,
+    record: &'a str,
+    stage: &'static str,
+    reason: String,
+}
+
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum RejectsFormat {
+    Csv,
+    Ndjson,
+}
+
+impl RejectsFormat {
+    /// NDJSON for `.ndjson` and `.jsonl` files, CSV otherwise.
+    pub fn from_path(fpath: &str) -> RejectsFormat {
+        if fpath.ends_with(".ndjson") || fpath.ends_with(".jsonl") {
+            RejectsFormat::Ndjson
+        } else {
+            RejectsFormat::Csv
+        }
+    }
+}
+
+impl FromStr for RejectsFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(RejectsFormat::Csv),
+            "ndjson" => Ok(RejectsFormat::Ndjson),
+            _ => Err(()),
+        }
+    }
+}
+
+enum RejectsOutput {
+    Csv(Box<csv::Writer<File>>),
+    Ndjson(BufWriter<File>),
+}
+
+/// Rejected rows report.
+pub struct RejectsWriter {
+    output: RejectsOutput,
+}
+
+impl RejectsWriter {
+    pub fn create(fpath: &str, format: RejectsFormat) -> std::io::Result<RejectsWriter> {
+        let file = File::create(fpath)?;
+        let output = match format {
+            RejectsFormat::Ndjson => RejectsOutput::Ndjson(BufWriter::new(file)),
+            RejectsFormat::Csv => RejectsOutput::Csv(Box::new(csv::Writer::from_writer(file))),
+        };
+        Ok(RejectsWriter{output})
+    }
+
+    pub fn write(&mut self, input: &str, row: u64, record: &str, cause: &RejectCause)
+            -> std::io::Result<()> {
+        let reject_row = RejectRow{
+            input,
+            row,
+            record,
+            stage: cause.stage(),
+            reason: cause.to_string(),
+        };
+        match &mut self.output {
+            RejectsOutput::Csv(writer) => writer.serialize(&reject_row)?,
+            RejectsOutput::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, &reject_row)?;
+                writer.write_all(b"\n")?;
+            },
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.output {
+            RejectsOutput::Csv(writer) => writer.flush(),
+            RejectsOutput::Ndjson(writer) => writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use crate::outcome::RejectReason;
+    use crate::rejects::{RejectCause, RejectsFormat, RejectsWriter};
+    use crate::tx::ValidationError;
+
+    #[test]
+    fn csv_rejects_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_rejects.csv");
+        let fpath = fpath.to_str().unwrap();
+        assert_eq!(RejectsFormat::from_path(fpath), RejectsFormat::Csv);
+        let mut writer = RejectsWriter::create(fpath, RejectsFormat::Csv).unwrap();
+        writer.write("day1.csv", 2, "deposit,1,1,0",
+            &RejectCause::Validation(ValidationError::NonPositiveAmount)).unwrap();
+        writer.write("day2.csv", 3, "withdrawal,1,2,5.0",
+            &RejectCause::Engine(RejectReason::InsufficientFunds)).unwrap();
+        writer.flush().unwrap();
+        let content = fs::read_to_string(fpath).unwrap();
+        fs::remove_file(fpath).unwrap();
+        assert_eq!(content, "input,row,record,stage,reason\n\
+            day1.csv,2,\"deposit,1,1,0\",validation,amount must be positive\n\
+            day2.csv,3,\"withdrawal,1,2,5.0\",engine,insufficient funds\n");
+    }
+
+    #[test]
+    fn ndjson_rejects_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_rejects.ndjson");
+        let fpath = fpath.to_str().unwrap();
+        assert_eq!(RejectsFormat::from_path(fpath), RejectsFormat::Ndjson);
+        let mut writer = RejectsWriter::create(fpath, RejectsFormat::Ndjson).unwrap();
+        writer.write("-", 4, "dispute,1,9", &RejectCause::Engine(RejectReason::UnknownTx)).unwrap();
+        writer.flush().unwrap();
+        let content = fs::read_to_string(fpath).unwrap();
+        fs::remove_file(fpath).unwrap();
+        assert_eq!(content, "{\"input\":\"-\",\"row\":4,\"record\":\"dispute,1,9\",\"stage\":\"engine\",\
+            \"reason\":\"unknown transaction\"}\n");
+    }
+}
@@ -0,0 +1,366 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Serialize;
+
+use crate::client::Client;
+use crate::currency::Currency;
+
+/// Format of the accounts report.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// One JSON array of accounts.
+    Json,
+    /// One JSON account per line.
+    Ndjson,
+    /// Columns aligned for reading in a terminal.
+    Table,
+}
+
+impl OutputFormat {
+    /// JSON for `.json` files, NDJSON for `.ndjson` and `.jsonl` files, CSV otherwise.
+    pub fn from_path(fpath: &str) -> OutputFormat {
+        if fpath.ends_with(".json") {
+            OutputFormat::Json
+        } else if fpath.ends_with(".ndjson") || fpath.ends_with(".jsonl") {
+            OutputFormat::Ndjson
+        } else {
+            OutputFormat::Csv
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "table" => Ok(OutputFormat::Table),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Rendering of the amounts in the report.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct AmountFormat {
+    /// Decimal places always printed, amounts are rounded half away from zero to them.
+    pub scale: u32,
+    /// Separator between groups of thousands of the integer part.
+    pub thousands_separator: Option<char>,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat{scale: 4, thousands_separator: None}
+    }
+}
+
+impl AmountFormat {
+    pub fn format(&self, amount: Decimal) -> String {
+        let mut amount = amount.round_dp_with_strategy(self.scale, RoundingStrategy::MidpointAwayFromZero);
+        amount.rescale(self.scale);
+        // an amount rounded to zero keeps its sign, -0 is not printed
+        if amount.is_zero() {
+            amount.set_sign_positive(true);
+        }
+        let formatted = amount.to_string();
+        let separator = match self.thousands_separator {
+            Some(separator) => separator,
+            None => return formatted,
+        };
+        let (sign, unsigned) = match formatted.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", formatted.as_str()),
+        };
+        let (integer, fraction) = match unsigned.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (unsigned, None),
+        };
+        let mut grouped = String::from(sign);
+        for (idx, digit) in integer.chars().enumerate() {
+            if idx > 0 && (integer.len() - idx) % 3 == 0 {
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            grouped.push('.');
+            grouped.push_str(fraction);
+        }
+        grouped
+    }
+}
+
+/// One account of the report, the columns in report order with the amounts formatted.
+#[derive(PartialEq)]
+#[derive(Debug, Serialize)]
+pub struct AccountRow {
+    pub client: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+    /// Fees collected from the account, only for accounts charged any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fees: Option<String>,
+    /// Only for accounts with a credit limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit_limit: Option<String>,
+}
+
+impl AccountRow {
+    pub fn new(client: &Client, amount_format: &AmountFormat) -> AccountRow {
+        AccountRow{
+            client: client.id,
+            currency: client.currency,
+            available: amount_format.format(client.available),
+            held: amount_format.format(client.held),
+            total: amount_format.format(client.total),
+            locked: client.locked,
+            fees: (!client.fees.is_zero()).then(|| amount_format.format(client.fees)),
+            credit_limit: (!client.credit_limit.is_zero()).then(|| amount_format.format(client.credit_limit)),
+        }
+    }
+
+    /// The column values of the report columns.
+    fn fields(&self, columns: &Columns) -> Vec<String> {
+        let mut fields = vec![self.client.to_string()];
+        if columns.currency {
+            fields.push(self.currency.map_or(String::new(), |currency| currency.to_string()));
+        }
+        fields.extend([self.available.clone(), self.held.clone(), self.total.clone(), self.locked.to_string()]);
+        if columns.fees {
+            fields.push(self.fees.clone().unwrap_or_default());
+        }
+        if columns.credit_limit {
+            fields.push(self.credit_limit.clone().unwrap_or_default());
+        }
+        fields
+    }
+}
+
+/// The optional columns a report has.
+struct Columns {
+    currency: bool,
+    fees: bool,
+    credit_limit: bool,
+}
+
+impl Columns {
+    fn of(clients: &[Client]) -> Columns {
+        Columns{
+            currency: clients.iter().any(|client| client.currency.is_some()),
+            fees: clients.iter().any(|client| !client.fees.is_zero()),
+            credit_limit: clients.iter().any(|client| !client.credit_limit.is_zero()),
+        }
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        let mut names = vec!["client"];
+        if self.currency {
+            names.push("currency");
+        }
+        names.extend(["available", "held", "total", "locked"]);
+        if self.fees {
+            names.push("fees");
+        }
+        if self.credit_limit {
+            names.push("credit_limit");
+        }
+        names
+    }
+}
+
+/// Writes the accounts report in the given format.
+///
+/// The accounts in a currency are reported with their currency code. CSV and table reports
+/// get a `currency` column only if there is such an account, the accounts without a
+/// currency are left empty in it.
+///
+/// Likewise the collected fees and the credit limits are reported only if any account has
+/// them, then for every account.
+pub fn write_accounts(clients: &[Client], format: OutputFormat, amount_format: &AmountFormat,
+        writer: &mut dyn Write) -> io::Result<()> {
+    let columns = Columns::of(clients);
+    let rows = clients.iter().map(|client| {
+        let mut row = AccountRow::new(client, amount_format);
+        if columns.fees {
+            row.fees = Some(amount_format.format(client.fees));
+        }
+        if columns.credit_limit {
+            row.credit_limit = Some(amount_format.format(client.credit_limit));
+        }
+        row
+    });
+    match format {
+        OutputFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            csv_writer.write_record(columns.names())?;
+            for row in rows {
+                csv_writer.write_record(row.fields(&columns))?;
+            }
+            csv_writer.flush()?;
+        },
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut *writer, &rows.collect::<Vec<AccountRow>>())?;
+            writer.write_all(b"\n")?;
+        },
+        OutputFormat::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut *writer, &row)?;
+                writer.write_all(b"\n")?;
+            }
+        },
+        OutputFormat::Table => {
+            let fields: Vec<Vec<String>> = rows.map(|row| row.fields(&columns)).collect();
+            let header: Vec<String> = columns.names().into_iter().map(str::to_string).collect();
+            // a thousands separator may take several bytes, the padding counts chars
+            let mut widths: Vec<usize> = header.iter().map(|name| name.chars().count()).collect();
+            for row in fields.iter() {
+                for (width, field) in widths.iter_mut().zip(row.iter()) {
+                    *width = (*width).max(field.chars().count());
+                }
+            }
+            for row in std::iter::once(&header).chain(fields.iter()) {
+                let line: Vec<String> = row.iter().zip(widths.iter())
+                    .map(|(field, width)| format!("{:>width$}", field, width = width))
+                    .collect();
+                writeln!(writer, "{}", line.join("  "))?;
+            }
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use crate::client::Client;
+    use crate::report::{write_accounts, AmountFormat, OutputFormat};
+
+    fn get_test_clients() -> Vec<Client> {
+        vec![
+            Client{id: 1, currency: None, available: Decimal::new(15, 1), held: Decimal::ZERO, total: Decimal::new(15, 1), fees: Decimal::ZERO, locked: false, credit_limit: Decimal::ZERO},
+            Client{id: 12, currency: None, available: Decimal::from(-2), held: Decimal::new(12345, 4), total: Decimal::new(-7655, 4), fees: Decimal::ZERO, locked: true, credit_limit: Decimal::ZERO},
+        ]
+    }
+
+    fn report(clients: &[Client], format: OutputFormat) -> String {
+        let mut output = Vec::new();
+        write_accounts(clients, format, &AmountFormat::default(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn output_formats_functionality() {
+        let clients = get_test_clients();
+        assert_eq!(report(&clients, OutputFormat::Csv), "client,available,held,total,locked\n\
+            1,1.5000,0.0000,1.5000,false\n\
+            12,-2.0000,1.2345,-0.7655,true\n");
+        assert_eq!(report(&clients, OutputFormat::Json), "[\
+            {\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false},\
+            {\"client\":12,\"available\":\"-2.0000\",\"held\":\"1.2345\",\"total\":\"-0.7655\",\"locked\":true}]\n");
+        assert_eq!(report(&clients, OutputFormat::Ndjson), "\
+            {\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}\n\
+            {\"client\":12,\"available\":\"-2.0000\",\"held\":\"1.2345\",\"total\":\"-0.7655\",\"locked\":true}\n");
+        assert_eq!(report(&clients, OutputFormat::Table), "\
+            client  available    held    total  locked\n     \
+                 1     1.5000  0.0000   1.5000   false\n    \
+                12    -2.0000  1.2345  -0.7655    true\n");
+    }
+
+    #[test]
+    fn amount_format_functionality() {
+        let amount_format = AmountFormat::default();
+        assert_eq!(amount_format.format(Decimal::new(15, 1)), "1.5000");
+        assert_eq!(amount_format.format(Decimal::new(150, 2)), "1.5000");
+        assert_eq!(amount_format.format(-Decimal::new(0, 3)), "0.0000");
+        assert_eq!(amount_format.format(Decimal::new(-4, 5)), "0.0000");
+        assert_eq!(amount_format.format(Decimal::new(-5, 5)), "-0.0001");
+
+        let amount_format = AmountFormat{scale: 2, thousands_separator: Some(',')};
+        assert_eq!(amount_format.format(Decimal::new(12345678905, 4)), "1,234,567.89");
+        assert_eq!(amount_format.format(Decimal::new(-1234565, 4)), "-123.46");
+        assert_eq!(amount_format.format(Decimal::new(-1000, 0)), "-1,000.00");
+        assert_eq!(amount_format.format(Decimal::new(-1, 3)), "0.00");
+
+        let amount_format = AmountFormat{scale: 0, thousands_separator: Some(' ')};
+        assert_eq!(amount_format.format(Decimal::new(1234565, 1)), "123 457");
+
+        let clients = get_test_clients();
+        let mut output = Vec::new();
+        write_accounts(&clients[1..], OutputFormat::Csv, &AmountFormat{scale: 1, thousands_separator: Some(',')},
+            &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n\
+            12,-2.0,1.2,-0.8,true\n");
+
+        let mut clients = get_test_clients();
+        clients[0].available = Decimal::from(1234567);
+        let mut output = Vec::new();
+        write_accounts(&clients, OutputFormat::Table, &AmountFormat{scale: 0, thousands_separator: Some('\u{2009}')},
+            &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\
+            client  available  held  total  locked\n     \
+                 1  1\u{2009}234\u{2009}567     0      2   false\n    \
+                12         -2     1     -1    true\n");
+    }
+
+    #[test]
+    fn currency_report_functionality() {
+        let mut clients = get_test_clients();
+        clients[1].currency = Some("EUR".parse().unwrap());
+        clients.push(Client{currency: Some("USD".parse().unwrap()), ..clients[1]});
+        assert_eq!(report(&clients, OutputFormat::Csv), "client,currency,available,held,total,locked\n\
+            1,,1.5000,0.0000,1.5000,false\n\
+            12,EUR,-2.0000,1.2345,-0.7655,true\n\
+            12,USD,-2.0000,1.2345,-0.7655,true\n");
+        assert_eq!(report(&clients[..2], OutputFormat::Ndjson), "\
+            {\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}\n\
+            {\"client\":12,\"currency\":\"EUR\",\"available\":\"-2.0000\",\"held\":\"1.2345\",\"total\":\"-0.7655\",\"locked\":true}\n");
+        assert_eq!(report(&clients[1..2], OutputFormat::Table), "\
+            client  currency  available    held    total  locked\n    \
+                12       EUR    -2.0000  1.2345  -0.7655    true\n");
+    }
+
+    #[test]
+    fn fees_report_functionality() {
+        let mut clients = get_test_clients();
+        clients[0].fees = Decimal::new(25, 2);
+        assert_eq!(report(&clients, OutputFormat::Csv), "client,available,held,total,locked,fees\n\
+            1,1.5000,0.0000,1.5000,false,0.2500\n\
+            12,-2.0000,1.2345,-0.7655,true,0.0000\n");
+        assert_eq!(report(&clients[..1], OutputFormat::Ndjson),
+            "{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false,\"fees\":\"0.2500\"}\n");
+    }
+
+    #[test]
+    fn credit_limit_report_functionality() {
+        let mut clients = get_test_clients();
+        clients[1].credit_limit = Decimal::from(5);
+        assert_eq!(report(&clients, OutputFormat::Csv), "client,available,held,total,locked,credit_limit\n\
+            1,1.5000,0.0000,1.5000,false,0.0000\n\
+            12,-2.0000,1.2345,-0.7655,true,5.0000\n");
+    }
+
+    #[test]
+    fn empty_report_functionality() {
+        assert_eq!(report(&[], OutputFormat::Csv), "client,available,held,total,locked\n");
+        assert_eq!(report(&[], OutputFormat::Json), "[]\n");
+        assert_eq!(report(&[], OutputFormat::Ndjson), "");
+        assert_eq!(OutputFormat::from_path("accounts.jsonl"), OutputFormat::Ndjson);
+        assert_eq!("table".parse::<OutputFormat>(), Ok(OutputFormat::Table));
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::panic;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::fees::FeeSchedule;
+use crate::outcome::RejectReason;
+use crate::payments_engine::{PaymentsEngine, LockPolicy};
+use crate::profiles::ClientProfile;
+use crate::tx::{Transaction, TransactionType};
+use crate::tx_database::RedisputePolicy;
+
+/// Transactions are handed over to the shards in batches to keep the channel overhead low.
+const BATCH_SIZE: usize = 1024;
+/// Batches queued per shard before `submit` blocks.
+const CHANNEL_BATCHES: usize = 64;
+
+/// Submitted transactions carry their submission sequence number.
+type Batch<K> = Vec<(Transaction, u64, K)>;
+/// The engine of a shard, the ids its accepted transactions stored and the transactions it
+/// rejected.
+type ShardResult<K> = (PaymentsEngine, Vec<(u32, u64, TxIdKind)>, Vec<(Transaction, u64, K, RejectReason)>);
+
+/// The merged engine and the rejected transactions with their keys.
+type EngineResult<K> = (PaymentsEngine, Vec<(K, RejectReason)>);
+
+/// What a transaction id refers to once a transaction of the type is accepted.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+enum TxIdKind {
+    Transaction,
+    Authorization,
+}
+
+impl TxIdKind {
+    fn stored_by(tx_type: TransactionType) -> Option<TxIdKind> {
+        match tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+                | TransactionType::Limit => Some(TxIdKind::Transaction),
+            // a capture is stored as a withdrawal next to its authorization
+            TransactionType::Capture => Some(TxIdKind::Transaction),
+            TransactionType::Authorize => Some(TxIdKind::Authorization),
+            _ => None,
+        }
+    }
+}
+
+struct Shard<K> {
+    sender: SyncSender<Batch<K>>,
+    batch: Batch<K>,
+    handle: JoinHandle<ShardResult<K>>,
+}
+
+/// In-memory payments engine split into shards by client id, each shard processed by its
+/// own thread.
+///
+/// All transactions of a client go to the same shard in submission order. A shard only
+/// sees the transaction ids of its own clients, so `finish` compares the ids accepted by
+/// all shards: a reference to an id of another shard is rejected with the reason of the
+/// sequential engine.
+///
+/// A transfer may change the account of a client on another shard, and a new transaction
+/// id already submitted for a client of another shard may be a duplicate. `submit` then
+/// merges the shards and processes this and all following transactions on the calling
+/// thread, so the result always equals the sequential engine.
+///
+/// Every transaction is submitted with a key, the keys of the rejected transactions are
+/// returned with the reason by `finish`.
+pub struct ShardedEngine<K: Send + 'static> {
+    shards: Vec<Shard<K>>,
+    shard_count: usize,
+    next_seq: u64,
+    /// Shard of every new transaction id submitted so far.
+    tx_id_shards: HashMap<u32, usize>,
+    /// The merged engine and its rejected transactions once the shards were given up.
+    sequential: Option<EngineResult<K>>,
+    lock_policy: LockPolicy,
+    redispute_policy: RedisputePolicy,
+    fee_schedule: FeeSchedule,
+}
+
+impl<K: Send + 'static> ShardedEngine<K> {
+    pub fn new(shard_count: usize, lock_policy: LockPolicy, redispute_policy: RedisputePolicy,
+            fee_schedule: FeeSchedule, profiles: &[ClientProfile]) -> ShardedEngine<K> {
+        let shard_count = shard_count.max(1);
+        let shards = (0..shard_count).map(|shard_idx| {
+            let (sender, receiver) = sync_channel::<Batch<K>>(CHANNEL_BATCHES);
+            let shard_fee_schedule = fee_schedule.clone();
+            let shard_profiles: Vec<ClientProfile> = profiles.iter()
+                .filter(|profile| profile.client as usize % shard_count == shard_idx)
+                .copied()
+                .collect();
+            let handle = thread::spawn(move || {
+                let mut payments_engine = PaymentsEngine::new();
+                payments_engine.set_lock_policy(lock_policy);
+                payments_engine.set_redispute_policy(redispute_policy);
+                payments_engine.set_fee_schedule(shard_fee_schedule);
+                if let Err(err) = payments_engine.apply_profiles(&shard_profiles) {
+                    panic!("shard {}: {}", shard_idx, err);
+                }
+                let mut accepted = Vec::new();
+                let mut rejected = Vec::new();
+                for batch in receiver.iter() {
+                    for (tx, seq, key) in batch {
+                        match payments_engine.process_transaction(&tx) {
+                            Ok(()) => if let Some(kind) = TxIdKind::stored_by(tx.tx_type) {
+                                accepted.push((tx.tx, seq, kind));
+                            },
+                            Err(reason) => rejected.push((tx, seq, key, reason)),
+                        }
+                    }
+                }
+                (payments_engine, accepted, rejected)
+            });
+            Shard{sender, batch: Vec::with_capacity(BATCH_SIZE), handle}
+        }).collect();
+        ShardedEngine{shards, shard_count, next_seq: 0, tx_id_shards: HashMap::new(), sequential: None,
+            lock_policy, redispute_policy, fee_schedule}
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shard_count
+    }
+
+    /// Whether the shards were merged and the transactions are processed on the calling thread.
+    pub fn is_sequential(&self) -> bool {
+        self.sequential.is_some()
+    }
+
+    /// Queues the transaction on the shard of its client, or processes it right away once
+    /// the shards were merged.
+    pub fn submit(&mut self, tx: Transaction, key: K) {
+        let shard_idx = tx.client as usize % self.shard_count;
+        if self.sequential.is_none() && !self.needs_all_shards(&tx, shard_idx) {
+            let shard = &mut self.shards[shard_idx];
+            shard.batch.push((tx, self.next_seq, key));
+            self.next_seq += 1;
+            if shard.batch.len() >= BATCH_SIZE {
+                Self::send_batch(shard);
+            }
+            return;
+        }
+        let (payments_engine, rejected) = self.sequential.get_or_insert_with(|| Self::merge(
+            std::mem::take(&mut self.shards), self.lock_policy, self.redispute_policy, &self.fee_schedule));
+        if let Err(reason) = payments_engine.process_transaction(&tx) {
+            rejected.push((key, reason));
+        }
+    }
+
+    /// Whether the transaction may depend on a client or transaction id of another shard.
+    fn needs_all_shards(&mut self, tx: &Transaction, shard_idx: usize) -> bool {
+        match tx.tx_type {
+            TransactionType::Transfer => true,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Limit
+                | TransactionType::Authorize => *self.tx_id_shards.entry(tx.tx).or_insert(shard_idx) != shard_idx,
+            _ => false,
+        }
+    }
+
+    fn send_batch(shard: &mut Shard<K>) {
+        let batch = std::mem::replace(&mut shard.batch, Vec::with_capacity(BATCH_SIZE));
+        // the receiver only goes away if the shard thread panicked, finish reports it
+        let _ = shard.sender.send(batch);
+    }
+
+    /// Waits for all submitted transactions and merges the shards into one engine.
+    pub fn finish(self) -> EngineResult<K> {
+        match self.sequential {
+            Some(engine_result) => engine_result,
+            None => Self::merge(self.shards, self.lock_policy, self.redispute_policy, &self.fee_schedule),
+        }
+    }
+
+    fn merge(shards: Vec<Shard<K>>, lock_policy: LockPolicy, redispute_policy: RedisputePolicy,
+            fee_schedule: &FeeSchedule) -> EngineResult<K> {
+        let mut merged_engine = PaymentsEngine::new();
+        merged_engine.set_lock_policy(lock_policy);
+        merged_engine.set_redispute_policy(redispute_policy);
+        merged_engine.set_fee_schedule(fee_schedule.clone());
+        let mut rejected = Vec::new();
+        let mut handles = Vec::new();
+        for mut shard in shards {
+            if !shard.batch.is_empty() {
+                Self::send_batch(&mut shard);
+            }
+            drop(shard.sender);
+            handles.push(shard.handle);
+        }
+        let mut shard_results = Vec::new();
+        for handle in handles {
+            match handle.join() {
+                Ok(shard_result) => shard_results.push(shard_result),
+                Err(err) => panic::resume_unwind(err),
+            }
+        }
+        let mut tx_ids: HashMap<u32, (usize, Vec<(u64, TxIdKind)>)> = HashMap::new();
+        for (shard_idx, (_, accepted, _)) in shard_results.iter().enumerate() {
+            for &(tx_id, seq, kind) in accepted.iter() {
+                // submit keeps the new ids of different shards apart
+                tx_ids.entry(tx_id).or_insert((shard_idx, Vec::new())).1.push((seq, kind));
+            }
+        }
+        for (shard_idx, (payments_engine, _, shard_rejected)) in shard_results.into_iter().enumerate() {
+            // shards hold disjoint clients and transactions
+            if let Err(err) = merged_engine.restore(&payments_engine.snapshot()) {
+                panic!("merging shards: {}", err);
+            }
+            rejected.extend(shard_rejected.into_iter().map(|(tx, seq, key, reason)| {
+                // an id stored earlier by another shard was not visible to this one
+                let other_kinds: Vec<TxIdKind> = tx_ids.get(&tx.tx).into_iter()
+                    .filter(|(owner_idx, _)| *owner_idx != shard_idx)
+                    .flat_map(|(_, kinds)| kinds.iter())
+                    .filter(|(stored_seq, _)| *stored_seq < seq)
+                    .map(|(_, kind)| *kind)
+                    .collect();
+                (key, Self::sequential_reason(&tx, reason, &other_kinds))
+            }));
+        }
+        (merged_engine, rejected)
+    }
+
+    /// The reason the sequential engine rejects the transaction with, given what another
+    /// shard stored under its transaction id before it.
+    fn sequential_reason(tx: &Transaction, reason: RejectReason, other_kinds: &[TxIdKind]) -> RejectReason {
+        let is_transaction = other_kinds.contains(&TxIdKind::Transaction);
+        let is_authorization = other_kinds.contains(&TxIdKind::Authorization);
+        match (tx.tx_type, reason) {
+            _ if other_kinds.is_empty() => reason,
+            // locked accounts are checked before the transaction id
+            (_, RejectReason::AccountLocked) => reason,
+            (TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+                | TransactionType::Limit | TransactionType::Authorize, _) => RejectReason::DuplicateTxId,
+            (TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback,
+                    RejectReason::UnknownTx) if is_transaction => RejectReason::ClientMismatch,
+            (TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback,
+                    RejectReason::UnknownTx) if is_authorization => RejectReason::NotDisputable,
+            (TransactionType::Capture | TransactionType::Void | TransactionType::Expire,
+                    RejectReason::UnknownTx) if is_authorization => RejectReason::ClientMismatch,
+            _ => reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use crate::fees::FeeSchedule;
+    use crate::outcome::RejectReason;
+    use crate::payments_engine::{PaymentsEngine, LockPolicy};
+    use crate::sharded::ShardedEngine;
+    use crate::tx::{Transaction, TransactionType};
+    use crate::tx_database::RedisputePolicy;
+
+    /// Deterministic mix of all transaction types over a few clients.
+    fn get_test_transactions(count: u32) -> Vec<Transaction> {
+        let mut seed: u64 = 42;
+        let mut txs = Vec::new();
+        for tx_id in 1..=count {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let client = ((seed >> 33) % 7) as u16;
+            let (tx_type, tx, amount) = match (seed >> 40) % 10 {
+                0..=3 => (TransactionType::Deposit, tx_id, Some(Decimal::new((seed >> 45) as i64 % 1000, 1))),
+                4..=6 => (TransactionType::Withdrawal, tx_id, Some(Decimal::new((seed >> 45) as i64 % 500, 1))),
+                7 => (TransactionType::Dispute, tx_id - (seed >> 50) as u32 % tx_id, None),
+                8 => (TransactionType::Resolve, tx_id - (seed >> 50) as u32 % tx_id, None),
+                _ => (TransactionType::Chargeback, tx_id - (seed >> 50) as u32 % tx_id, None),
+            };
+            txs.push(Transaction{tx_type, client, tx, amount, currency: None, to: None});
+        }
+        txs
+    }
+
+    #[test]
+    fn sharded_equals_sequential_functionality() {
+        let txs = get_test_transactions(20_000);
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.set_lock_policy(LockPolicy::AllowDisputes);
+        let mut sequential_rejected = Vec::new();
+        for (idx, tx) in txs.iter().enumerate() {
+            if let Err(reason) = payments_engine.process_transaction(tx) {
+                sequential_rejected.push((idx, reason));
+            }
+        }
+
+        let mut sharded_engine = ShardedEngine::new(4, LockPolicy::AllowDisputes,
+            RedisputePolicy::Never, FeeSchedule::default(), &[]);
+        assert_eq!(sharded_engine.shard_count(), 4);
+        for (idx, tx) in txs.iter().enumerate() {
+            sharded_engine.submit(*tx, idx);
+        }
+        let (merged_engine, mut sharded_rejected) = sharded_engine.finish();
+        sharded_rejected.sort_by_key(|(idx, _)| *idx);
+
+        assert_eq!(merged_engine.snapshot(), payments_engine.snapshot());
+        assert_eq!(merged_engine.lock_policy(), LockPolicy::AllowDisputes);
+        assert!(sequential_rejected.iter().any(|(_, reason)| *reason == RejectReason::ClientMismatch));
+        assert_eq!(sharded_rejected, sequential_rejected);
+    }
+
+    #[test]
+    fn sharded_tx_id_reuse_functionality() {
+        let tx = |tx_type, client, tx, amount: i64| Transaction{
+            tx_type,
+            client,
+            tx,
+            amount: Some(Decimal::from(amount)),
+            currency: None,
+            to: None,
+        };
+        let txs = [
+            tx(TransactionType::Withdrawal, 1, 1, 5),
+            tx(TransactionType::Deposit, 2, 1, 7),
+            tx(TransactionType::Withdrawal, 1, 1, 1),
+            tx(TransactionType::Deposit, 1, 9, 1),
+            tx(TransactionType::Authorize, 1, 2, 1),
+            Transaction{amount: None, ..tx(TransactionType::Dispute, 2, 2, 0)},
+            Transaction{amount: None, ..tx(TransactionType::Capture, 2, 2, 0)},
+            Transaction{amount: None, ..tx(TransactionType::Dispute, 1, 1, 0)},
+            Transaction{amount: None, ..tx(TransactionType::Dispute, 2, 3, 0)},
+            tx(TransactionType::Deposit, 2, 3, 1),
+            Transaction{amount: None, ..tx(TransactionType::Capture, 1, 2, 0)},
+            Transaction{amount: None, ..tx(TransactionType::Dispute, 2, 2, 0)},
+            Transaction{amount: None, ..tx(TransactionType::Void, 2, 2, 0)},
+        ];
+        let mut payments_engine = PaymentsEngine::new();
+        let mut sharded_engine = ShardedEngine::new(2, LockPolicy::default(), RedisputePolicy::default(),
+            FeeSchedule::default(), &[]);
+        let mut sequential_rejected = Vec::new();
+        for (idx, tx) in txs.iter().enumerate() {
+            if let Err(reason) = payments_engine.process_transaction(tx) {
+                sequential_rejected.push((idx, reason));
+            }
+            sharded_engine.submit(*tx, idx);
+        }
+        let (merged_engine, mut sharded_rejected) = sharded_engine.finish();
+        sharded_rejected.sort_by_key(|(idx, _)| *idx);
+        assert_eq!(sequential_rejected, vec![
+            (0, RejectReason::InsufficientFunds),
+            (2, RejectReason::DuplicateTxId),
+            (5, RejectReason::NotDisputable),
+            (6, RejectReason::ClientMismatch),
+            (7, RejectReason::ClientMismatch),
+            (8, RejectReason::UnknownTx),
+            (11, RejectReason::ClientMismatch),
+            (12, RejectReason::ClientMismatch),
+        ]);
+        assert_eq!(sharded_rejected, sequential_rejected);
+        assert_eq!(merged_engine.snapshot(), payments_engine.snapshot());
+
+        // the deposit of the second client could be a duplicate, the shards are given up
+        let mut sharded_engine = ShardedEngine::new(2, LockPolicy::default(), RedisputePolicy::default(),
+            FeeSchedule::default(), &[]);
+        sharded_engine.submit(tx(TransactionType::Deposit, 1, 1, 5), 0);
+        assert!(!sharded_engine.is_sequential());
+        sharded_engine.submit(tx(TransactionType::Deposit, 2, 1, 7), 1);
+        assert!(sharded_engine.is_sequential());
+        sharded_engine.submit(tx(TransactionType::Deposit, 2, 2, 7), 2);
+        let (merged_engine, rejected) = sharded_engine.finish();
+        assert_eq!(rejected, vec![(1, RejectReason::DuplicateTxId)]);
+        assert_eq!(merged_engine.client(1).unwrap().total, Decimal::from(5));
+        assert_eq!(merged_engine.client(2).unwrap().total, Decimal::from(7));
+    }
+
+    #[test]
+    fn sharded_transfers_functionality() {
+        let tx = |tx_type, client, tx, to| Transaction{
+            tx_type,
+            client,
+            tx,
+            amount: Some(Decimal::from(5)),
+            currency: None,
+            to,
+        };
+        let txs = [
+            tx(TransactionType::Deposit, 1, 1, None),
+            tx(TransactionType::Deposit, 2, 2, None),
+            tx(TransactionType::Transfer, 1, 3, Some(4)),
+            tx(TransactionType::Transfer, 2, 4, Some(2)),
+            tx(TransactionType::Withdrawal, 4, 5, None),
+            tx(TransactionType::Transfer, 1, 6, Some(3)),
+            tx(TransactionType::Deposit, 3, 7, None),
+        ];
+        let mut payments_engine = PaymentsEngine::new();
+        let mut sharded_engine = ShardedEngine::new(2, LockPolicy::default(), RedisputePolicy::default(),
+            FeeSchedule::default(), &[]);
+        let mut sequential_rejected = Vec::new();
+        for (idx, tx) in txs.iter().enumerate() {
+            if let Err(reason) = payments_engine.process_transaction(tx) {
+                sequential_rejected.push((idx, reason));
+            }
+            sharded_engine.submit(*tx, idx);
+            // the destination client may live on another shard
+            assert_eq!(sharded_engine.is_sequential(), idx >= 2);
+        }
+        let (merged_engine, mut sharded_rejected) = sharded_engine.finish();
+        sharded_rejected.sort_by_key(|(idx, _)| *idx);
+        assert_eq!(sequential_rejected, vec![
+            (3, RejectReason::SelfTransfer),
+            (5, RejectReason::InsufficientFunds),
+        ]);
+        assert_eq!(sharded_rejected, sequential_rejected);
+        assert_eq!(merged_engine.snapshot(), payments_engine.snapshot());
+        assert_eq!(merged_engine.client(4).unwrap().total, Decimal::ZERO);
+        assert_eq!(merged_engine.client(3).unwrap().total, Decimal::from(5));
+    }
+}
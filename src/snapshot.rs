@@ -0,0 +1,204 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::Client;
+use crate::tx_database::{Authorization, StoredTransaction};
+
+/// Version of the snapshot file format written by this build.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Full engine state: client accounts, stored transactions with their lifecycle state and
+/// authorizations.
+#[derive(PartialEq)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Sequence number of the last write-ahead log record included in the snapshot.
+    #[serde(default)]
+    pub wal_seq: u64,
+    pub clients: Vec<Client>,
+    pub transactions: Vec<StoredTransaction>,
+    #[serde(default)]
+    pub authorizations: Vec<Authorization>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot io error: {}", err),
+            SnapshotError::Format(err) => write!(f, "snapshot format error: {}", err),
+            SnapshotError::UnsupportedVersion(version) => write!(f,
+                "unsupported snapshot version {}, expected {}", version, SNAPSHOT_VERSION),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(err: serde_json::Error) -> Self {
+        SnapshotError::Format(err)
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+impl Snapshot {
+    /// Writes the snapshot next to the target file first and renames it over the target,
+    /// so a crash never leaves a half written snapshot behind.
+    pub fn save<P: AsRef<Path>>(&self, fpath: P) -> Result<(), SnapshotError> {
+        let fpath = fpath.as_ref();
+        let mut tmp_path = fpath.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, fpath)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(fpath: P) -> Result<Snapshot, SnapshotError> {
+        let content = fs::read_to_string(fpath)?;
+        // the version is checked before the rest of the format is interpreted
+        let header: SnapshotHeader = serde_json::from_str(&content)?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(header.version));
+        }
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use rust_decimal::Decimal;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::snapshot::{Snapshot, SnapshotError};
+    use crate::tx::{Transaction, TransactionType};
+
+    fn get_test_payments_engine() -> PaymentsEngine {
+        let mut engine = PaymentsEngine::new();
+        let mut tx = Transaction{
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::new(25, 1)),
+            currency: None,
+            to: None,
+        };
+        engine.process_transaction(&tx).unwrap();
+        tx.tx = 2;
+        tx.client = 2;
+        engine.process_transaction(&tx).unwrap();
+        tx.tx_type = TransactionType::Dispute;
+        tx.amount = None;
+        engine.process_transaction(&tx).unwrap();
+        engine
+    }
+
+    #[test]
+    fn snapshot_restore_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_snapshot.json");
+        let engine = get_test_payments_engine();
+        engine.snapshot().save(&fpath).unwrap();
+        let snapshot = Snapshot::load(&fpath).unwrap();
+        fs::remove_file(&fpath).unwrap();
+        assert_eq!(snapshot, engine.snapshot());
+
+        let mut restored_engine = PaymentsEngine::new();
+        restored_engine.restore(&snapshot).unwrap();
+        assert_eq!(restored_engine.snapshot(), engine.snapshot());
+        // the restored dispute can be resolved
+        let tx = Transaction{
+            tx_type: TransactionType::Resolve,
+            client: 2,
+            tx: 2,
+            amount: None,
+            currency: None,
+            to: None,
+        };
+        restored_engine.process_transaction(&tx).unwrap();
+        assert_eq!(restored_engine.client(2).unwrap().available, Decimal::new(25, 1));
+    }
+
+    #[test]
+    fn snapshot_engine_state_functionality() {
+        use crate::fees::{FeeRule, FeeSchedule};
+        use crate::store::TransactionStore;
+        use crate::tx_database::{AuthState, TxState};
+
+        let fpath = env::temp_dir().join("toy_payments_engine_snapshot_state.json");
+        let mut engine = PaymentsEngine::new();
+        engine.set_fee_schedule(FeeSchedule{
+            deposit: Some(FeeRule{flat: Decimal::ONE, ..FeeRule::default()}),
+            withdrawal: None,
+        });
+        let tx = |tx_type, tx, amount: Option<i64>| Transaction{
+            tx_type,
+            client: 1,
+            tx,
+            amount: amount.map(Decimal::from),
+            currency: None,
+            to: None,
+        };
+        for tx in [
+            tx(TransactionType::Deposit, 1, Some(11)),
+            tx(TransactionType::Authorize, 2, Some(4)),
+            tx(TransactionType::Authorize, 3, Some(1)),
+            tx(TransactionType::Capture, 2, Some(3)),
+            tx(TransactionType::Dispute, 1, Some(5)),
+        ] {
+            engine.process_transaction(&tx).unwrap();
+        }
+        engine.snapshot().save(&fpath).unwrap();
+        let snapshot = Snapshot::load(&fpath).unwrap();
+        fs::remove_file(&fpath).unwrap();
+        assert_eq!(snapshot, engine.snapshot());
+        assert_eq!(snapshot.authorizations.len(), 2);
+
+        // fees, authorizations and disputed parts carry over
+        let mut restored_engine = PaymentsEngine::new();
+        restored_engine.restore(&snapshot).unwrap();
+        assert_eq!(restored_engine.snapshot(), snapshot);
+        assert_eq!(restored_engine.tx_db().get_authorization(&2).unwrap().state, AuthState::Captured);
+        restored_engine.process_transaction(&tx(TransactionType::Void, 3, None)).unwrap();
+        restored_engine.process_transaction(&tx(TransactionType::Chargeback, 1, None)).unwrap();
+        let stored_tx = restored_engine.tx_db().get_tx(&1).unwrap();
+        assert_eq!((stored_tx.state, stored_tx.charged_back), (TxState::PartiallyChargedBack, Decimal::from(5)));
+        let client = restored_engine.client(1).unwrap();
+        assert_eq!((client.available, client.held, client.total), (Decimal::from(2), Decimal::ZERO, Decimal::from(2)));
+    }
+
+    #[test]
+    fn snapshot_version_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_snapshot_v0.json");
+        fs::write(&fpath, "{\"version\":0,\"accounts\":[]}").unwrap();
+        let res = Snapshot::load(&fpath);
+        fs::remove_file(&fpath).unwrap();
+        assert!(matches!(res, Err(SnapshotError::UnsupportedVersion(0))));
+    }
+}
@@ -0,0 +1,585 @@
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use rust_decimal::Decimal;
+
+use crate::client::Client;
+use crate::currency::Currency;
+use crate::store::{ClientStore, TransactionStore};
+use crate::tx::{Transaction, TransactionType};
+use crate::tx_database::{AuthState, Authorization, StoredTransaction, TxState};
+
+// the currency of the accounts without one is stored as an empty string, it is part of
+// the primary key
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS clients (
+        id INTEGER NOT NULL,
+        currency TEXT NOT NULL DEFAULT '',
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        fees TEXT NOT NULL DEFAULT '0',
+        credit_limit TEXT NOT NULL DEFAULT '0',
+        PRIMARY KEY (id, currency)
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        id INTEGER PRIMARY KEY,
+        tx_type TEXT NOT NULL,
+        client INTEGER NOT NULL,
+        amount TEXT,
+        state TEXT NOT NULL,
+        currency TEXT,
+        fee TEXT NOT NULL DEFAULT '0',
+        to_client INTEGER,
+        disputed TEXT NOT NULL DEFAULT '0',
+        charged_back TEXT NOT NULL DEFAULT '0'
+    );
+    CREATE TABLE IF NOT EXISTS authorizations (
+        id INTEGER PRIMARY KEY,
+        client INTEGER NOT NULL,
+        amount TEXT NOT NULL,
+        currency TEXT,
+        state TEXT NOT NULL,
+        captured TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS engine_state (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        wal_seq INTEGER NOT NULL
+    );";
+
+/// Upgrades a database created before accounts had currencies.
+const MIGRATE_CURRENCIES: &str = "
+    ALTER TABLE clients RENAME TO clients_without_currency;
+    CREATE TABLE clients (
+        id INTEGER NOT NULL,
+        currency TEXT NOT NULL DEFAULT '',
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        PRIMARY KEY (id, currency)
+    );
+    INSERT INTO clients (id, available, held, total, locked)
+        SELECT id, available, held, total, locked FROM clients_without_currency;
+    DROP TABLE clients_without_currency;
+    ALTER TABLE transactions ADD COLUMN currency TEXT;";
+
+/// Upgrades a database created before fees were charged.
+const MIGRATE_FEES: &str = "
+    ALTER TABLE clients ADD COLUMN fees TEXT NOT NULL DEFAULT '0';
+    ALTER TABLE transactions ADD COLUMN fee TEXT NOT NULL DEFAULT '0';";
+
+/// Upgrades a database created before transfers.
+const MIGRATE_TRANSFERS: &str = "
+    ALTER TABLE transactions ADD COLUMN to_client INTEGER;";
+
+/// Upgrades a database created before partial disputes.
+const MIGRATE_PARTIAL_DISPUTES: &str = "
+    ALTER TABLE transactions ADD COLUMN disputed TEXT NOT NULL DEFAULT '0';
+    ALTER TABLE transactions ADD COLUMN charged_back TEXT NOT NULL DEFAULT '0';";
+
+/// Upgrades a database created before credit limits.
+const MIGRATE_CREDIT_LIMITS: &str = "
+    ALTER TABLE clients ADD COLUMN credit_limit TEXT NOT NULL DEFAULT '0';";
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Opens (or creates) the SQLite database file and returns the client and transaction
+/// stores sharing its connection.
+pub fn open_sqlite_stores<P: AsRef<Path>>(fpath: P)
+        -> rusqlite::Result<(SqliteClientStore, SqliteTransactionStore)> {
+    let mut conn = Connection::open(fpath)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.execute_batch(SCHEMA)?;
+    if !has_column(&conn, "clients", "currency")? {
+        let migration = conn.transaction()?;
+        migration.execute_batch(MIGRATE_CURRENCIES)?;
+        migration.commit()?;
+    }
+    if !has_column(&conn, "clients", "fees")? {
+        let migration = conn.transaction()?;
+        migration.execute_batch(MIGRATE_FEES)?;
+        migration.commit()?;
+    }
+    if !has_column(&conn, "transactions", "to_client")? {
+        conn.execute_batch(MIGRATE_TRANSFERS)?;
+    }
+    if !has_column(&conn, "clients", "credit_limit")? {
+        conn.execute_batch(MIGRATE_CREDIT_LIMITS)?;
+    }
+    if !has_column(&conn, "transactions", "disputed")? {
+        let migration = conn.transaction()?;
+        migration.execute_batch(MIGRATE_PARTIAL_DISPUTES)?;
+        migration.commit()?;
+    }
+    let conn = Rc::new(conn);
+    Ok((SqliteClientStore{conn: conn.clone()}, SqliteTransactionStore{conn}))
+}
+
+// decimals are stored as text to keep them exact
+fn parse_decimal(value: String) -> Decimal {
+    match value.parse() {
+        Ok(val) => val,
+        Err(_) => panic!("sqlite store: corrupted decimal {}", value),
+    }
+}
+
+fn parse_currency(value: String) -> Option<Currency> {
+    if value.is_empty() {
+        return None;
+    }
+    match value.parse() {
+        Ok(val) => Some(val),
+        Err(_) => panic!("sqlite store: corrupted currency {}", value),
+    }
+}
+
+fn currency_column(currency: Option<Currency>) -> String {
+    currency.map_or(String::new(), |currency| currency.to_string())
+}
+
+fn check<T>(res: rusqlite::Result<T>) -> T {
+    match res {
+        Ok(val) => val,
+        Err(err) => panic!("sqlite store: {}", err),
+    }
+}
+
+fn client_from_row(row: &Row) -> rusqlite::Result<Client> {
+    Ok(Client {
+        id: row.get(0)?,
+        currency: parse_currency(row.get(1)?),
+        available: parse_decimal(row.get(2)?),
+        held: parse_decimal(row.get(3)?),
+        total: parse_decimal(row.get(4)?),
+        fees: parse_decimal(row.get(6)?),
+        locked: row.get(5)?,
+        credit_limit: parse_decimal(row.get(7)?),
+    })
+}
+
+fn stored_tx_from_row(row: &Row) -> rusqlite::Result<StoredTransaction> {
+    let tx_type: String = row.get(1)?;
+    let amount: Option<String> = row.get(3)?;
+    let state: String = row.get(4)?;
+    let currency: Option<String> = row.get(5)?;
+    let fee: String = row.get(6)?;
+    let tx = Transaction {
+        tx_type: match tx_type.parse::<TransactionType>() {
+            Ok(val) => val,
+            Err(_) => panic!("sqlite store: corrupted transaction type {}", tx_type),
+        },
+        client: row.get(2)?,
+        tx: row.get(0)?,
+        amount: amount.map(parse_decimal),
+        currency: currency.and_then(parse_currency),
+        to: row.get(7)?,
+    };
+    let state = match state.parse::<TxState>() {
+        Ok(val) => val,
+        Err(_) => panic!("sqlite store: corrupted transaction state {}", state),
+    };
+    Ok(StoredTransaction{tx, state, fee: parse_decimal(fee), disputed: parse_decimal(row.get(8)?),
+        charged_back: parse_decimal(row.get(9)?)})
+}
+
+fn authorization_from_row(row: &Row) -> rusqlite::Result<Authorization> {
+    let currency: Option<String> = row.get(3)?;
+    let state: String = row.get(4)?;
+    let tx = Transaction {
+        tx_type: TransactionType::Authorize,
+        client: row.get(1)?,
+        tx: row.get(0)?,
+        amount: Some(parse_decimal(row.get(2)?)),
+        currency: currency.and_then(parse_currency),
+        to: None,
+    };
+    let state = match state.parse::<AuthState>() {
+        Ok(val) => val,
+        Err(_) => panic!("sqlite store: corrupted authorization state {}", state),
+    };
+    Ok(Authorization{tx, state, captured: parse_decimal(row.get(5)?)})
+}
+
+/// Client store persisted in SQLite.
+pub struct SqliteClientStore {
+    conn: Rc<Connection>,
+}
+
+impl ClientStore for SqliteClientStore {
+    fn client(&self, client_id: u16, currency: Option<Currency>) -> Option<Client> {
+        let mut stmt = check(self.conn.prepare_cached(
+            "SELECT id, currency, available, held, total, locked, fees, credit_limit FROM clients
+             WHERE id = ?1 AND currency = ?2"));
+        check(stmt.query_row(params![client_id, currency_column(currency)], client_from_row).optional())
+    }
+
+    fn get_client(&mut self, client_id: u16, currency: Option<Currency>) -> Client {
+        if let Some(client) = self.client(client_id, currency) {
+            client
+        } else {
+            let new_client = Client::new(client_id, currency);
+            self.update_client(&new_client);
+            new_client
+        }
+    }
+
+    fn update_client(&mut self, client: &Client) {
+        let mut stmt = check(self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO clients (id, currency, available, held, total, locked, fees, credit_limit)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"));
+        check(stmt.execute(params![client.id, currency_column(client.currency),
+            client.available.to_string(), client.held.to_string(), client.total.to_string(),
+            client.locked, client.fees.to_string(), client.credit_limit.to_string()]));
+    }
+
+    fn clients(&self) -> Vec<Client> {
+        let mut stmt = check(self.conn.prepare_cached(
+            "SELECT id, currency, available, held, total, locked, fees, credit_limit FROM clients ORDER BY id, currency"));
+        let rows = check(stmt.query_map([], client_from_row));
+        rows.map(check).collect()
+    }
+
+    fn client_accounts(&self, client_id: u16) -> Vec<Client> {
+        let mut stmt = check(self.conn.prepare_cached(
+            "SELECT id, currency, available, held, total, locked, fees, credit_limit FROM clients
+             WHERE id = ?1 ORDER BY currency"));
+        let rows = check(stmt.query_map(params![client_id], client_from_row));
+        rows.map(check).collect()
+    }
+}
+
+/// Transaction store persisted in SQLite.
+///
+/// `begin` and `commit` open and commit a SQLite transaction on the connection shared with
+/// the `SqliteClientStore`, so a processed transaction and the client update it causes are
+/// persisted atomically.
+pub struct SqliteTransactionStore {
+    conn: Rc<Connection>,
+}
+
+impl TransactionStore for SqliteTransactionStore {
+    fn begin(&mut self) -> io::Result<()> {
+        self.conn.execute_batch("BEGIN IMMEDIATE").map_err(io::Error::other)
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        self.conn.execute_batch("COMMIT").map_err(io::Error::other)
+    }
+
+    fn rollback(&mut self) -> io::Result<()> {
+        self.conn.execute_batch("ROLLBACK").map_err(io::Error::other)
+    }
+
+    fn wal_seq(&self) -> u64 {
+        let mut stmt = check(self.conn.prepare_cached("SELECT wal_seq FROM engine_state WHERE id = 1"));
+        check(stmt.query_row([], |row| row.get(0)).optional()).unwrap_or(0)
+    }
+
+    fn set_wal_seq(&mut self, seq: u64) {
+        let mut stmt = check(self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO engine_state (id, wal_seq) VALUES (1, ?1)"));
+        check(stmt.execute(params![seq]));
+    }
+
+    fn get_tx(&self, tx_id: &u32) -> Option<StoredTransaction> {
+        let mut stmt = check(self.conn.prepare_cached(
+            "SELECT id, tx_type, client, amount, state, currency, fee, to_client, disputed, charged_back FROM transactions WHERE id = ?1"));
+        check(stmt.query_row(params![tx_id], stored_tx_from_row).optional())
+    }
+
+    fn add_tx(&mut self, tx: &Transaction, fee: Decimal) {
+        let mut stmt = check(self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO transactions (id, tx_type, client, amount, state, currency, fee, to_client)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"));
+        check(stmt.execute(params![tx.tx, tx.tx_type.as_str(), tx.client,
+            tx.amount.map(|amount| amount.to_string()), TxState::Processed.as_str(),
+            tx.currency.map(|currency| currency.to_string()), fee.to_string(), tx.to]));
+    }
+
+    fn set_state(&mut self, tx_id: &u32, state: TxState) {
+        let mut stmt = check(self.conn.prepare_cached(
+            "UPDATE transactions SET state = ?2 WHERE id = ?1"));
+        check(stmt.execute(params![tx_id, state.as_str()]));
+    }
+
+    fn set_dispute_amounts(&mut self, tx_id: &u32, disputed: Decimal, charged_back: Decimal) {
+        let mut stmt = check(self.conn.prepare_cached(
+            "UPDATE transactions SET disputed = ?2, charged_back = ?3 WHERE id = ?1"));
+        check(stmt.execute(params![tx_id, disputed.to_string(), charged_back.to_string()]));
+    }
+
+    fn transactions(&self) -> Vec<StoredTransaction> {
+        let mut stmt = check(self.conn.prepare_cached(
+            "SELECT id, tx_type, client, amount, state, currency, fee, to_client, disputed, charged_back FROM transactions ORDER BY id"));
+        let rows = check(stmt.query_map([], stored_tx_from_row));
+        rows.map(check).collect()
+    }
+
+    fn get_authorization(&self, tx_id: &u32) -> Option<Authorization> {
+        let mut stmt = check(self.conn.prepare_cached(
+            "SELECT id, client, amount, currency, state, captured FROM authorizations WHERE id = ?1"));
+        check(stmt.query_row(params![tx_id], authorization_from_row).optional())
+    }
+
+    fn set_authorization(&mut self, authorization: &Authorization) {
+        let tx = &authorization.tx;
+        let mut stmt = check(self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO authorizations (id, client, amount, currency, state, captured)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)"));
+        check(stmt.execute(params![tx.tx, tx.client, tx.amount.unwrap_or_default().to_string(),
+            tx.currency.map(|currency| currency.to_string()), authorization.state.as_str(),
+            authorization.captured.to_string()]));
+    }
+
+    fn authorizations(&self) -> Vec<Authorization> {
+        let mut stmt = check(self.conn.prepare_cached(
+            "SELECT id, client, amount, currency, state, captured FROM authorizations ORDER BY id"));
+        let rows = check(stmt.query_map([], authorization_from_row));
+        rows.map(check).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use rust_decimal::Decimal;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::sqlite_store::open_sqlite_stores;
+    use crate::store::TransactionStore;
+    use crate::tx::{Transaction, TransactionType};
+    use crate::tx_database::TxState;
+
+    #[test]
+    fn sqlite_resume_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_resume.sqlite");
+        let _ = fs::remove_file(&fpath);
+        {
+            let (client_db, tx_db) = open_sqlite_stores(&fpath).unwrap();
+            let mut engine = PaymentsEngine::with_stores(client_db, tx_db);
+            let mut tx = Transaction{
+                tx_type: TransactionType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(Decimal::new(15, 1)),
+                currency: None,
+                to: None,
+            };
+            engine.process_transaction(&tx).unwrap();
+            tx.tx_type = TransactionType::Dispute;
+            tx.amount = None;
+            engine.process_transaction(&tx).unwrap();
+        }
+        let (client_db, tx_db) = open_sqlite_stores(&fpath).unwrap();
+        let mut engine = PaymentsEngine::with_stores(client_db, tx_db);
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.available, Decimal::from(0));
+        assert_eq!(client.held, Decimal::new(15, 1));
+        assert_eq!(client.total, Decimal::new(15, 1));
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().state, TxState::Disputed);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().disputed, Decimal::new(15, 1));
+        let tx = Transaction{
+            tx_type: TransactionType::Chargeback,
+            client: 1,
+            tx: 1,
+            amount: None,
+            currency: None,
+            to: None,
+        };
+        engine.process_transaction(&tx).unwrap();
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.total, Decimal::from(0));
+        assert!(client.locked);
+        assert_eq!(engine.tx_db().transactions().len(), 1);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().charged_back, Decimal::new(15, 1));
+        drop(engine);
+        fs::remove_file(&fpath).unwrap();
+    }
+
+    #[test]
+    fn sqlite_wal_functionality() {
+        use crate::tx_database::RedisputePolicy;
+        use crate::wal::{WalRecord, WriteAheadLog};
+
+        let fpath = env::temp_dir().join("toy_payments_engine_wal.sqlite");
+        let wal_path = env::temp_dir().join("toy_payments_engine_wal.wal");
+        let _ = fs::remove_file(&fpath);
+        let _ = fs::remove_file(&wal_path);
+        let expected_snapshot;
+        {
+            let (client_db, tx_db) = open_sqlite_stores(&fpath).unwrap();
+            let mut engine = PaymentsEngine::with_stores(client_db, tx_db);
+            engine.set_redispute_policy(RedisputePolicy::AfterResolve);
+            assert_eq!(engine.recover_from_wal(&wal_path).unwrap(), 0);
+            let mut tx = Transaction{
+                tx_type: TransactionType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(Decimal::from(5)),
+                currency: None,
+                to: None,
+            };
+            engine.process_transaction(&tx).unwrap();
+            tx.amount = None;
+            tx.tx_type = TransactionType::Dispute;
+            engine.process_transaction(&tx).unwrap();
+            tx.tx_type = TransactionType::Resolve;
+            engine.process_transaction(&tx).unwrap();
+            expected_snapshot = engine.snapshot();
+        }
+        // a record logged but not committed before a crash
+        let (mut wal, records) = WriteAheadLog::open(&wal_path).unwrap();
+        assert_eq!(records.len(), 3);
+        let tx = Transaction{
+            tx_type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 2,
+            amount: Some(Decimal::from(2)),
+            currency: None,
+            to: None,
+        };
+        wal.append(&WalRecord{seq: 4, tx}).unwrap();
+        drop(wal);
+
+        // the committed records are not replayed again
+        let (client_db, tx_db) = open_sqlite_stores(&fpath).unwrap();
+        let mut engine = PaymentsEngine::with_stores(client_db, tx_db);
+        engine.set_redispute_policy(RedisputePolicy::AfterResolve);
+        assert_eq!(engine.wal_seq(), 3);
+        assert_eq!(engine.snapshot(), expected_snapshot);
+        assert_eq!(engine.recover_from_wal(&wal_path).unwrap(), 1);
+        assert_eq!(engine.client(1).unwrap().total, Decimal::from(3));
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().state, TxState::Resolved);
+        drop(engine);
+        let (_, tx_db) = open_sqlite_stores(&fpath).unwrap();
+        assert_eq!(tx_db.wal_seq(), 4);
+        drop(tx_db);
+        fs::remove_file(&fpath).unwrap();
+        fs::remove_file(&wal_path).unwrap();
+    }
+
+    #[test]
+    fn sqlite_rollback_functionality() {
+        use std::panic::{self, AssertUnwindSafe};
+        use rusqlite::Connection;
+        use crate::currency::Currency;
+        use crate::outcome::RejectReason;
+        use crate::store::ClientStore;
+
+        let fpath = env::temp_dir().join("toy_payments_engine_rollback.sqlite");
+        let _ = fs::remove_file(&fpath);
+        let (client_db, tx_db) = open_sqlite_stores(&fpath).unwrap();
+        let mut engine = PaymentsEngine::with_stores(client_db, tx_db);
+        let eur: Option<Currency> = Some("EUR".parse().unwrap());
+        let mut tx = Transaction{
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::from(2)),
+            currency: eur,
+            to: None,
+        };
+        engine.process_transaction(&tx).unwrap();
+        // a rejected transaction does not open an account in its currency
+        tx.tx_type = TransactionType::Withdrawal;
+        tx.tx = 2;
+        tx.currency = None;
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.client_db().client_accounts(1).len(), 1);
+        // but a client seen for the first time is reported
+        tx.client = 2;
+        assert_eq!(engine.process_transaction(&tx), Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.client(2).unwrap().total, Decimal::ZERO);
+
+        // a panicking store leaves no open SQLite transaction behind
+        let conn = Connection::open(&fpath).unwrap();
+        conn.execute_batch("ALTER TABLE transactions RENAME TO moved").unwrap();
+        tx.tx_type = TransactionType::Deposit;
+        tx.tx = 3;
+        tx.client = 1;
+        tx.currency = eur;
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| engine.process_transaction(&tx))).is_err());
+        conn.execute_batch("ALTER TABLE moved RENAME TO transactions").unwrap();
+        assert_eq!(engine.account(1, eur).unwrap().total, Decimal::from(2));
+        engine.process_transaction(&tx).unwrap();
+        assert_eq!(engine.account(1, eur).unwrap().total, Decimal::from(4));
+        drop(conn);
+        drop(engine);
+        fs::remove_file(&fpath).unwrap();
+    }
+
+    #[test]
+    fn sqlite_currency_migration_functionality() {
+        use rusqlite::Connection;
+        use crate::currency::Currency;
+        use crate::store::ClientStore;
+
+        let fpath = env::temp_dir().join("toy_payments_engine_migration.sqlite");
+        let _ = fs::remove_file(&fpath);
+        {
+            // schema before accounts had currencies
+            let conn = Connection::open(&fpath).unwrap();
+            conn.execute_batch("
+                CREATE TABLE clients (id INTEGER PRIMARY KEY, available TEXT NOT NULL,
+                    held TEXT NOT NULL, total TEXT NOT NULL, locked INTEGER NOT NULL);
+                CREATE TABLE transactions (id INTEGER PRIMARY KEY, tx_type TEXT NOT NULL,
+                    client INTEGER NOT NULL, amount TEXT, state TEXT NOT NULL);
+                INSERT INTO clients VALUES (1, '2.5', '0', '2.5', 0);
+                INSERT INTO transactions VALUES (1, 'deposit', 1, '2.5', 'processed');").unwrap();
+        }
+        let (client_db, tx_db) = open_sqlite_stores(&fpath).unwrap();
+        let mut engine = PaymentsEngine::with_stores(client_db, tx_db);
+        assert_eq!(engine.client(1).unwrap().total, Decimal::new(25, 1));
+        let eur: Option<Currency> = Some("EUR".parse().unwrap());
+        let tx = Transaction{
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 2,
+            amount: Some(Decimal::from(4)),
+            currency: eur,
+            to: None,
+        };
+        engine.process_transaction(&tx).unwrap();
+        assert_eq!(engine.tx_db().get_tx(&2).unwrap().tx, tx);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().tx.currency, None);
+        let accounts = engine.client_db().client_accounts(1);
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[1].currency, eur);
+        assert_eq!(accounts[1].total, Decimal::from(4));
+        // databases before fees get their columns too
+        assert_eq!(accounts[0].fees, Decimal::ZERO);
+        assert_eq!(accounts[0].credit_limit, Decimal::ZERO);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().fee, Decimal::ZERO);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().tx.to, None);
+        assert_eq!(engine.tx_db().get_tx(&1).unwrap().charged_back, Decimal::ZERO);
+        let tx = Transaction{
+            tx_type: TransactionType::Authorize,
+            client: 1,
+            tx: 3,
+            amount: Some(Decimal::from(1)),
+            currency: eur,
+            to: None,
+        };
+        engine.process_transaction(&tx).unwrap();
+        assert_eq!(engine.tx_db().authorizations()[0].tx, tx);
+        drop(engine);
+        // opening an upgraded database again keeps it as is
+        let (client_db, _) = open_sqlite_stores(&fpath).unwrap();
+        assert_eq!(client_db.clients().len(), 2);
+        drop(client_db);
+        fs::remove_file(&fpath).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use std::io::{self, Write};
+
+use rust_decimal::Decimal;
+
+use crate::client::Client;
+use crate::currency::Currency;
+use crate::report::{self, AmountFormat, OutputFormat};
+use crate::tx::Transaction;
+use crate::tx_database::{Authorization, StoredTransaction, TxState};
+
+/// Storage of client accounts used by the payments engine, one account per client and
+/// currency.
+///
+/// The engine treats reads and updates as infallible, implementations backed by IO panic
+/// on unrecoverable backend errors.
+pub trait ClientStore {
+    /// Returns the account of the client in the currency without creating it.
+    fn client(&self, client_id: u16, currency: Option<Currency>) -> Option<Client>;
+
+    /// Returns the account of the client in the currency, a new empty account is created
+    /// if there is none.
+    fn get_client(&mut self, client_id: u16, currency: Option<Currency>) -> Client;
+
+    fn update_client(&mut self, client: &Client);
+
+    /// Returns all accounts ordered by client id and currency.
+    fn clients(&self) -> Vec<Client>;
+
+    /// Returns the accounts of the client ordered by currency.
+    fn client_accounts(&self, client_id: u16) -> Vec<Client> {
+        self.clients().into_iter().filter(|client| client.id == client_id).collect()
+    }
+
+    /// Writes the accounts report in the given format.
+    fn write_report(&self, writer: &mut dyn Write, format: OutputFormat, amount_format: &AmountFormat)
+            -> io::Result<()> {
+        report::write_accounts(&self.clients(), format, amount_format, writer)
+    }
+
+    /// Writes the accounts report as CSV with four decimal places.
+    fn write_all(&self, writer: &mut dyn Write) -> io::Result<()> {
+        self.write_report(writer, OutputFormat::Csv, &AmountFormat::default())
+    }
+
+    fn print_all(&self) {
+        let stdout = io::stdout();
+        if let Err(err) = self.write_all(&mut stdout.lock()) {
+            panic!("can not print accounts: {}", err);
+        }
+    }
+}
+
+/// Storage of processed transactions and their lifecycle state.
+///
+/// The engine wraps every processed transaction into `begin` and `commit`, or `rollback`
+/// if the transaction is rejected or processing fails. A store sharing its backend with
+/// the client store commits the client updates made in between atomically with the
+/// transaction, see `SqliteTransactionStore`. Their errors are returned to the caller of
+/// the engine.
+pub trait TransactionStore {
+    fn begin(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Sequence number of the last write-ahead log record applied to the store, stores which
+    /// do not persist it return zero.
+    fn wal_seq(&self) -> u64 {
+        0
+    }
+
+    /// Records the write-ahead log record applied by the current transaction.
+    fn set_wal_seq(&mut self, _seq: u64) {}
+
+    fn get_tx(&self, tx_id: &u32) -> Option<StoredTransaction>;
+
+    /// Stores a new transaction in the processed state with the fee charged for it.
+    fn add_tx(&mut self, tx: &Transaction, fee: Decimal);
+
+    fn is_tx_exists(&self, tx_id: &u32) -> bool {
+        self.get_tx(tx_id).is_some()
+    }
+
+    fn set_state(&mut self, tx_id: &u32, state: TxState);
+
+    /// Sets the amount under dispute and the amount charged back so far.
+    fn set_dispute_amounts(&mut self, tx_id: &u32, disputed: Decimal, charged_back: Decimal);
+
+    /// Returns all stored transactions ordered by id.
+    fn transactions(&self) -> Vec<StoredTransaction>;
+
+    fn get_authorization(&self, tx_id: &u32) -> Option<Authorization>;
+
+    /// Stores a new authorization or updates the state of a stored one.
+    fn set_authorization(&mut self, authorization: &Authorization);
+
+    /// Returns all authorizations ordered by id.
+    fn authorizations(&self) -> Vec<Authorization>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io;
+    use std::rc::Rc;
+    use rust_decimal::Decimal;
+    use crate::client::Client;
+    use crate::currency::Currency;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::store::{ClientStore, TransactionStore};
+    use crate::tx::{Transaction, TransactionType};
+    use crate::tx_database::{Authorization, StoredTransaction, TxState};
+
+    /// Unordered stores implementing only the required methods.
+    #[derive(Default)]
+    struct VecClientStore(Vec<Client>);
+
+    /// The shared flag makes the commits fail.
+    #[derive(Default)]
+    struct VecTransactionStore(Vec<StoredTransaction>, Vec<Authorization>, Rc<Cell<bool>>);
+
+    impl ClientStore for VecClientStore {
+        fn client(&self, client_id: u16, currency: Option<Currency>) -> Option<Client> {
+            self.0.iter().find(|client| client.id == client_id && client.currency == currency).copied()
+        }
+
+        fn get_client(&mut self, client_id: u16, currency: Option<Currency>) -> Client {
+            match self.client(client_id, currency) {
+                Some(client) => client,
+                None => {
+                    let client = Client::new(client_id, currency);
+                    self.0.push(client);
+                    client
+                },
+            }
+        }
+
+        fn update_client(&mut self, client: &Client) {
+            self.0.retain(|stored| (stored.id, stored.currency) != (client.id, client.currency));
+            self.0.push(*client);
+        }
+
+        fn clients(&self) -> Vec<Client> {
+            let mut clients = self.0.clone();
+            clients.sort_by_key(|client| (client.id, client.currency));
+            clients
+        }
+    }
+
+    impl TransactionStore for VecTransactionStore {
+        fn commit(&mut self) -> io::Result<()> {
+            match self.2.get() {
+                true => Err(io::Error::other("disk full")),
+                false => Ok(()),
+            }
+        }
+
+        fn get_tx(&self, tx_id: &u32) -> Option<StoredTransaction> {
+            self.0.iter().find(|stored_tx| stored_tx.tx.tx == *tx_id).copied()
+        }
+
+        fn add_tx(&mut self, tx: &Transaction, fee: Decimal) {
+            self.0.push(StoredTransaction{tx: *tx, state: TxState::Processed, fee,
+                disputed: Decimal::ZERO, charged_back: Decimal::ZERO});
+        }
+
+        fn set_state(&mut self, tx_id: &u32, state: TxState) {
+            if let Some(stored_tx) = self.0.iter_mut().find(|stored_tx| stored_tx.tx.tx == *tx_id) {
+                stored_tx.state = state;
+            }
+        }
+
+        fn set_dispute_amounts(&mut self, tx_id: &u32, disputed: Decimal, charged_back: Decimal) {
+            if let Some(stored_tx) = self.0.iter_mut().find(|stored_tx| stored_tx.tx.tx == *tx_id) {
+                stored_tx.disputed = disputed;
+                stored_tx.charged_back = charged_back;
+            }
+        }
+
+        fn transactions(&self) -> Vec<StoredTransaction> {
+            let mut transactions = self.0.clone();
+            transactions.sort_by_key(|stored_tx| stored_tx.tx.tx);
+            transactions
+        }
+
+        fn get_authorization(&self, tx_id: &u32) -> Option<Authorization> {
+            self.1.iter().find(|authorization| authorization.tx.tx == *tx_id).copied()
+        }
+
+        fn set_authorization(&mut self, authorization: &Authorization) {
+            self.1.retain(|stored| stored.tx.tx != authorization.tx.tx);
+            self.1.push(*authorization);
+        }
+
+        fn authorizations(&self) -> Vec<Authorization> {
+            let mut authorizations = self.1.clone();
+            authorizations.sort_by_key(|authorization| authorization.tx.tx);
+            authorizations
+        }
+    }
+
+    #[test]
+    fn custom_store_functionality() {
+        let mut engine = PaymentsEngine::with_stores(VecClientStore::default(),
+            VecTransactionStore::default());
+        let mut default_engine = PaymentsEngine::new();
+        let txs = [
+            (TransactionType::Deposit, 1, 1, Some(10)),
+            (TransactionType::Deposit, 2, 2, Some(5)),
+            (TransactionType::Withdrawal, 1, 3, Some(4)),
+            (TransactionType::Withdrawal, 2, 4, Some(6)),
+            (TransactionType::Authorize, 2, 5, Some(2)),
+            (TransactionType::Dispute, 1, 1, None),
+            (TransactionType::Chargeback, 1, 1, None),
+            (TransactionType::Deposit, 1, 6, Some(1)),
+        ];
+        for (tx_type, client, tx, amount) in txs {
+            let tx = Transaction{
+                tx_type,
+                client,
+                tx,
+                amount: amount.map(Decimal::from),
+                currency: None,
+                to: None,
+            };
+            assert_eq!(engine.process_transaction(&tx), default_engine.process_transaction(&tx));
+        }
+        assert_eq!(engine.snapshot(), default_engine.snapshot());
+        assert!(engine.client(1).unwrap().locked);
+        assert_eq!(engine.client(2).unwrap().held, Decimal::from(2));
+    }
+
+    #[test]
+    fn failed_commit_functionality() {
+        use std::env;
+        use std::fs;
+        use crate::outcome::EngineError;
+
+        let wal_path = env::temp_dir().join("toy_payments_engine_failed_commit.wal");
+        let _ = fs::remove_file(&wal_path);
+        let tx_db = VecTransactionStore::default();
+        let failing_commits = tx_db.2.clone();
+        let mut engine = PaymentsEngine::with_stores(VecClientStore::default(), tx_db);
+        engine.recover_from_wal(&wal_path).unwrap();
+        let mut tx = Transaction{
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::ONE),
+            currency: None,
+            to: None,
+        };
+        assert_eq!(engine.try_process_transaction(&tx).unwrap(), Ok(()));
+        assert_eq!(engine.wal_seq(), 1);
+        // the logged record is not applied, the sequence number stays at the committed one
+        failing_commits.set(true);
+        tx.tx = 2;
+        assert!(matches!(engine.try_process_transaction(&tx), Err(EngineError::Storage(_))));
+        assert_eq!(engine.wal_seq(), 1);
+        fs::remove_file(&wal_path).unwrap();
+    }
+}
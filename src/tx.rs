@@ -1,9 +1,17 @@
-use serde::Deserialize;
-use rust_decimal::Decimal;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::currency::Currency;
+
+/// Decimal places of the amounts the engine works with.
+pub const AMOUNT_SCALE: u32 = 4;
 
 #[derive(PartialEq)]
 #[derive(Copy, Clone)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -11,39 +19,190 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Moves funds from the client to the `to` client.
+    Transfer,
+    /// Admin transaction setting the credit limit of the client to the amount.
+    Limit,
+    /// Moves the amount from `available` to `held` until it is captured or released.
+    Authorize,
+    /// Settles the referenced authorization as a withdrawal of the amount, the whole
+    /// authorization if the amount is left out, and releases the rest.
+    Capture,
+    /// Releases the referenced authorization.
+    Void,
+    /// Releases the referenced authorization when it runs out.
+    Expire,
 }
 
 #[derive(PartialEq)]
 #[derive(Copy, Clone)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
     pub client: u16,
     pub tx: u32,
     pub amount: Option<Decimal>,
+    /// Currency of a deposit or withdrawal, disputes and their follow-ups apply in the
+    /// currency of the referenced transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// Destination client of a transfer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<u16>,
+}
+
+impl TransactionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Limit => "limit",
+            TransactionType::Authorize => "authorize",
+            TransactionType::Capture => "capture",
+            TransactionType::Void => "void",
+            TransactionType::Expire => "expire",
+        }
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdrawal),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            "transfer" => Ok(TransactionType::Transfer),
+            "limit" => Ok(TransactionType::Limit),
+            "authorize" => Ok(TransactionType::Authorize),
+            "capture" => Ok(TransactionType::Capture),
+            "void" => Ok(TransactionType::Void),
+            "expire" => Ok(TransactionType::Expire),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What validation does with amounts of more than `AMOUNT_SCALE` decimal places.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Default)]
+pub enum PrecisionPolicy {
+    /// The transaction is rejected.
+    Reject,
+    /// Rounded half away from zero.
+    RoundHalfUp,
+    /// Rounded half to even, the banker's rounding.
+    #[default]
+    RoundHalfEven,
+    /// The extra decimal places are dropped.
+    Truncate,
+    /// The amount is kept with its full precision.
+    Exact,
+}
+
+impl FromStr for PrecisionPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(PrecisionPolicy::Reject),
+            "round-half-up" => Ok(PrecisionPolicy::RoundHalfUp),
+            "round-half-even" => Ok(PrecisionPolicy::RoundHalfEven),
+            "truncate" => Ok(PrecisionPolicy::Truncate),
+            "exact" => Ok(PrecisionPolicy::Exact),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Reason why a parsed transaction failed input validation.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum ValidationError {
+    MissingAmount,
+    NonPositiveAmount,
+    /// A credit limit below zero.
+    NegativeAmount,
+    /// More decimal places than `AMOUNT_SCALE` under `PrecisionPolicy::Reject`.
+    TooPrecise,
+    /// A transfer without a destination client.
+    MissingDestination,
+    /// A transfer to its own client.
+    SelfTransfer,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::MissingAmount => write!(f, "amount is missing"),
+            ValidationError::NonPositiveAmount => write!(f, "amount must be positive"),
+            ValidationError::NegativeAmount => write!(f, "amount must not be negative"),
+            ValidationError::TooPrecise => write!(f, "amount has more than {} decimal places", AMOUNT_SCALE),
+            ValidationError::MissingDestination => write!(f, "destination client is missing"),
+            ValidationError::SelfTransfer => write!(f, "transfer to the same client"),
+        }
+    }
 }
 
 impl Transaction {
-    pub fn validate(&mut self) -> bool {
+    /// Validates with the default precision policy, rounding half to even.
+    pub fn validate(&mut self) -> Result<(), ValidationError> {
+        self.validate_with(PrecisionPolicy::default())
+    }
+
+    pub fn validate_with(&mut self, precision_policy: PrecisionPolicy) -> Result<(), ValidationError> {
         // input validation
-        if self.tx_type == TransactionType::Deposit ||
-                self.tx_type == TransactionType::Withdrawal {
-            if self.amount.is_some() {
-                let amount = self.amount.unwrap().round_dp(4);
-                if amount > Decimal::from(0) {
-                    self.amount = Some(amount);
-                    return true;
-                } else {
-                    // amount must be positive
-                    return false;
-                }
-            } else {
-                // amoust must be positive
-                return false;
+        if self.tx_type == TransactionType::Transfer {
+            match self.to {
+                Some(to) if to == self.client => return Err(ValidationError::SelfTransfer),
+                Some(_) => {},
+                None => return Err(ValidationError::MissingDestination),
             }
         }
-        true
+        let amount_required = self.tx_type == TransactionType::Deposit ||
+            self.tx_type == TransactionType::Withdrawal ||
+            self.tx_type == TransactionType::Transfer ||
+            self.tx_type == TransactionType::Limit ||
+            self.tx_type == TransactionType::Authorize;
+        // a capture or dispute without an amount acts on the whole referenced amount
+        let amount_optional = self.tx_type == TransactionType::Capture ||
+            self.tx_type == TransactionType::Dispute;
+        if amount_required || (amount_optional && self.amount.is_some()) {
+            let amount = match self.amount {
+                Some(val) => val,
+                None => return Err(ValidationError::MissingAmount),
+            };
+            let amount = match precision_policy {
+                _ if amount.normalize().scale() <= AMOUNT_SCALE => amount,
+                PrecisionPolicy::Reject => return Err(ValidationError::TooPrecise),
+                PrecisionPolicy::RoundHalfUp =>
+                    amount.round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::MidpointAwayFromZero),
+                PrecisionPolicy::RoundHalfEven =>
+                    amount.round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::MidpointNearestEven),
+                PrecisionPolicy::Truncate =>
+                    amount.round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::ToZero),
+                PrecisionPolicy::Exact => amount,
+            };
+            // a zero credit limit removes the credit
+            if self.tx_type == TransactionType::Limit && amount < Decimal::from(0) {
+                return Err(ValidationError::NegativeAmount);
+            }
+            if self.tx_type != TransactionType::Limit && amount <= Decimal::from(0) {
+                return Err(ValidationError::NonPositiveAmount);
+            }
+            self.amount = Some(amount);
+        }
+        Ok(())
     }
 }
 
@@ -51,7 +210,7 @@ impl Transaction {
 mod tests {
     use rust_decimal::Decimal;
     use rust_decimal::prelude::FromPrimitive;
-    use crate::tx::{Transaction, TransactionType};
+    use crate::tx::{PrecisionPolicy, Transaction, TransactionType, ValidationError};
 
     #[test]
     fn transaction_max4digits_functionality() {
@@ -60,8 +219,10 @@ mod tests {
             client: 1,
             tx: 1,
             amount: Some(Decimal::from_f64(1.123456).unwrap()),
+            currency: None,
+            to: None,
         };
-        assert_eq!(tx.validate(), true);
+        assert_eq!(tx.validate(), Ok(()));
         assert_eq!(tx.amount.unwrap(), Decimal::from_f64(1.1235).unwrap());
     }
 
@@ -72,9 +233,102 @@ mod tests {
             client: 1,
             tx: 1,
             amount: Some(Decimal::from(1)),
+            currency: None,
+            to: None,
         };
-        assert_eq!(tx.validate(), true);
+        assert_eq!(tx.validate(), Ok(()));
         tx.amount = Some(Decimal::from(0));
-        assert_eq!(tx.validate(), false);
+        assert_eq!(tx.validate(), Err(ValidationError::NonPositiveAmount));
+        tx.amount = None;
+        assert_eq!(tx.validate(), Err(ValidationError::MissingAmount));
+    }
+
+    #[test]
+    fn transfer_validation_functionality() {
+        let mut tx = Transaction{
+            tx_type: TransactionType::Transfer,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::from(1)),
+            currency: None,
+            to: None,
+        };
+        assert_eq!(tx.validate(), Err(ValidationError::MissingDestination));
+        tx.to = Some(1);
+        assert_eq!(tx.validate(), Err(ValidationError::SelfTransfer));
+        tx.to = Some(2);
+        assert_eq!(tx.validate(), Ok(()));
+        tx.amount = None;
+        assert_eq!(tx.validate(), Err(ValidationError::MissingAmount));
+        assert_eq!("transfer".parse::<TransactionType>(), Ok(TransactionType::Transfer));
+    }
+
+    #[test]
+    fn capture_validation_functionality() {
+        let mut tx = Transaction{
+            tx_type: TransactionType::Capture,
+            client: 1,
+            tx: 1,
+            amount: None,
+            currency: None,
+            to: None,
+        };
+        assert_eq!(tx.validate(), Ok(()));
+        tx.amount = Some(Decimal::ZERO);
+        assert_eq!(tx.validate(), Err(ValidationError::NonPositiveAmount));
+        tx.amount = Some(Decimal::new(123455, 5));
+        assert_eq!(tx.validate(), Ok(()));
+        assert_eq!(tx.amount, Some(Decimal::new(12346, 4)));
+        tx.tx_type = TransactionType::Authorize;
+        tx.amount = None;
+        assert_eq!(tx.validate(), Err(ValidationError::MissingAmount));
+        assert_eq!("expire".parse::<TransactionType>(), Ok(TransactionType::Expire));
+    }
+
+    #[test]
+    fn limit_validation_functionality() {
+        let mut tx = Transaction{
+            tx_type: TransactionType::Limit,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::ZERO),
+            currency: None,
+            to: None,
+        };
+        assert_eq!(tx.validate(), Ok(()));
+        tx.amount = Some(Decimal::from(-1));
+        assert_eq!(tx.validate(), Err(ValidationError::NegativeAmount));
+        tx.amount = None;
+        assert_eq!(tx.validate(), Err(ValidationError::MissingAmount));
+    }
+
+    #[test]
+    fn precision_policy_functionality() {
+        let mut tx = Transaction{
+            tx_type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: Some(Decimal::new(123455, 5)),
+            currency: None,
+            to: None,
+        };
+        let validated = |mut tx: Transaction, policy| tx.validate_with(policy).map(|_| tx.amount.unwrap());
+        assert_eq!(validated(tx, PrecisionPolicy::Reject), Err(ValidationError::TooPrecise));
+        assert_eq!(validated(tx, PrecisionPolicy::RoundHalfUp), Ok(Decimal::new(12346, 4)));
+        assert_eq!(validated(tx, PrecisionPolicy::RoundHalfEven), Ok(Decimal::new(12346, 4)));
+        assert_eq!(validated(tx, PrecisionPolicy::Truncate), Ok(Decimal::new(12345, 4)));
+        assert_eq!(validated(tx, PrecisionPolicy::Exact), Ok(Decimal::new(123455, 5)));
+
+        tx.amount = Some(Decimal::new(123465, 5));
+        assert_eq!(validated(tx, PrecisionPolicy::RoundHalfUp), Ok(Decimal::new(12347, 4)));
+        assert_eq!(validated(tx, PrecisionPolicy::RoundHalfEven), Ok(Decimal::new(12346, 4)));
+
+        // trailing zeros are not extra precision
+        tx.amount = Some(Decimal::new(1500000, 6));
+        assert_eq!(validated(tx, PrecisionPolicy::Reject), Ok(Decimal::new(1500000, 6)));
+        tx.amount = Some(Decimal::new(4, 5));
+        assert_eq!(validated(tx, PrecisionPolicy::Truncate), Err(ValidationError::NonPositiveAmount));
+        assert_eq!(validated(tx, PrecisionPolicy::Exact), Ok(Decimal::new(4, 5)));
+        assert_eq!("round-half-up".parse::<PrecisionPolicy>(), Ok(PrecisionPolicy::RoundHalfUp));
     }
 }
@@ -1,90 +1,320 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeMap;
+use std::str::FromStr;
 
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::outcome::RejectReason;
+use crate::store::TransactionStore;
 use crate::tx::Transaction;
 
+/// Lifecycle state of a stored transaction.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    /// Part of the amount was charged back, the rest can be disputed again.
+    PartiallyChargedBack,
+}
+
+impl TxState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxState::Processed => "processed",
+            TxState::Disputed => "disputed",
+            TxState::Resolved => "resolved",
+            TxState::ChargedBack => "charged_back",
+            TxState::PartiallyChargedBack => "partially_charged_back",
+        }
+    }
+}
+
+impl FromStr for TxState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "processed" => Ok(TxState::Processed),
+            "disputed" => Ok(TxState::Disputed),
+            "resolved" => Ok(TxState::Resolved),
+            "charged_back" => Ok(TxState::ChargedBack),
+            "partially_charged_back" => Ok(TxState::PartiallyChargedBack),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Decides whether a resolved transaction can be disputed again.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Default)]
+pub enum RedisputePolicy {
+    /// A transaction can be disputed only once.
+    #[default]
+    Never,
+    /// A resolved transaction can be disputed again.
+    AfterResolve,
+}
+
+impl FromStr for RedisputePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(RedisputePolicy::Never),
+            "after-resolve" => Ok(RedisputePolicy::AfterResolve),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TxState {
+    /// The only place where the legal lifecycle transitions are defined.
+    pub fn transition(self, to: TxState, redispute_policy: RedisputePolicy)
+            -> Result<TxState, RejectReason> {
+        match (self, to) {
+            (TxState::ChargedBack, _) => Err(RejectReason::AlreadyChargedBack),
+            (_, TxState::Processed) => Err(RejectReason::DuplicateTxId),
+            (TxState::Processed, TxState::Disputed) => Ok(to),
+            (TxState::PartiallyChargedBack, TxState::Disputed) => Ok(to),
+            (TxState::Resolved, TxState::Disputed) => match redispute_policy {
+                RedisputePolicy::Never => Err(RejectReason::AlreadyDisputed),
+                RedisputePolicy::AfterResolve => Ok(to),
+            },
+            (TxState::Disputed, TxState::Disputed) => Err(RejectReason::AlreadyDisputed),
+            (TxState::Disputed, _) => Ok(to),
+            (_, _) => Err(RejectReason::NotUnderDispute),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredTransaction {
+    pub tx: Transaction,
+    pub state: TxState,
+    /// Fee charged with the transaction, refunded once it is fully charged back.
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub fee: Decimal,
+    /// Amount under the current dispute.
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub disputed: Decimal,
+    /// Amount charged back by earlier partial chargebacks.
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub charged_back: Decimal,
+}
+
+impl StoredTransaction {
+    /// Amount that can still be disputed.
+    pub fn remaining(&self) -> Decimal {
+        self.tx.amount.unwrap_or_default() - self.charged_back
+    }
+
+    /// Amount a resolve or chargeback acts on. Disputes stored before partial disputes
+    /// hold the whole remaining amount.
+    pub fn disputed_amount(&self) -> Decimal {
+        if self.disputed.is_zero() {
+            self.remaining()
+        } else {
+            self.disputed
+        }
+    }
+}
+
+/// Lifecycle state of an authorization.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthState {
+    Authorized,
+    Captured,
+    Voided,
+    Expired,
+}
+
+impl AuthState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthState::Authorized => "authorized",
+            AuthState::Captured => "captured",
+            AuthState::Voided => "voided",
+            AuthState::Expired => "expired",
+        }
+    }
+}
+
+impl FromStr for AuthState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "authorized" => Ok(AuthState::Authorized),
+            "captured" => Ok(AuthState::Captured),
+            "voided" => Ok(AuthState::Voided),
+            "expired" => Ok(AuthState::Expired),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Funds held by an `authorize` transaction, tracked apart from the processed transactions
+/// and their disputes.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Authorization {
+    /// The `authorize` transaction.
+    pub tx: Transaction,
+    pub state: AuthState,
+    /// Amount settled by the capture.
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub captured: Decimal,
+}
+
+/// In-memory transaction store, the default storage of the payments engine.
 pub struct TransactionDatabase {
-    pub db: BTreeMap<u32, Transaction>,
-    pub disputes: BTreeSet<u32>,
+    db: BTreeMap<u32, StoredTransaction>,
+    authorizations: BTreeMap<u32, Authorization>,
+}
+
+impl Default for TransactionDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TransactionDatabase {
-    pub fn get_tx(&mut self, tx_id: &u32) -> Option<Transaction> {
-        self.db.get(tx_id).cloned()
+    pub fn new() -> TransactionDatabase {
+        TransactionDatabase{db: BTreeMap::new(), authorizations: BTreeMap::new()}
     }
+}
 
-    pub fn add_tx(&mut self, tx: &Transaction) {
-        self.db.insert(tx.tx, *tx);
+impl TransactionStore for TransactionDatabase {
+    fn get_tx(&self, tx_id: &u32) -> Option<StoredTransaction> {
+        self.db.get(tx_id).copied()
     }
 
-    pub fn remove_tx(&mut self, tx_id: &u32) {
-        self.db.remove(tx_id);
+    fn add_tx(&mut self, tx: &Transaction, fee: Decimal) {
+        self.db.insert(tx.tx, StoredTransaction{tx: *tx, state: TxState::Processed, fee,
+            disputed: Decimal::ZERO, charged_back: Decimal::ZERO});
     }
 
-    pub fn is_tx_exists(&mut self, tx_id: &u32) -> bool {
-        let tx_maybe = self.get_tx(tx_id);
-        if tx_maybe.is_some() {
-            true
-        } else {
-            false
+    fn is_tx_exists(&self, tx_id: &u32) -> bool {
+        self.db.contains_key(tx_id)
+    }
+
+    fn set_state(&mut self, tx_id: &u32, state: TxState) {
+        if let Some(stored_tx) = self.db.get_mut(tx_id) {
+            stored_tx.state = state;
         }
     }
 
-    pub fn is_under_dispute(&mut self, tx_id: &u32) -> bool {
-        let val_maybe = self.disputes.get(tx_id);
-        if val_maybe.is_some() {
-            true
-        } else {
-            false
+    fn set_dispute_amounts(&mut self, tx_id: &u32, disputed: Decimal, charged_back: Decimal) {
+        if let Some(stored_tx) = self.db.get_mut(tx_id) {
+            stored_tx.disputed = disputed;
+            stored_tx.charged_back = charged_back;
         }
     }
 
-    pub fn create_dispute(&mut self, tx_id: &u32) {
-        self.disputes.insert(*tx_id);
+    fn transactions(&self) -> Vec<StoredTransaction> {
+        self.db.values().copied().collect()
+    }
+
+    fn get_authorization(&self, tx_id: &u32) -> Option<Authorization> {
+        self.authorizations.get(tx_id).copied()
     }
 
-    pub fn remove_dispute(&mut self, tx_id: &u32) {
-        self.disputes.remove(tx_id);
+    fn set_authorization(&mut self, authorization: &Authorization) {
+        self.authorizations.insert(authorization.tx.tx, *authorization);
+    }
+
+    fn authorizations(&self) -> Vec<Authorization> {
+        self.authorizations.values().copied().collect()
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
-    use std::collections::{BTreeMap, BTreeSet};
     use rust_decimal::Decimal;
-    use crate::tx_database::TransactionDatabase;
+    use crate::outcome::RejectReason;
+    use crate::store::TransactionStore;
+    use crate::tx_database::{TransactionDatabase, TxState, RedisputePolicy};
     use crate::tx::{Transaction, TransactionType};
 
     #[test]
     fn basic_database_functionality() {
-        let mut tx_db = TransactionDatabase{db: BTreeMap::new(), disputes: BTreeSet::new()};
+        let mut tx_db = TransactionDatabase::new();
         let tx = Transaction{
             tx_type: TransactionType::Deposit,
             client: 1,
             tx: 1,
             amount: Some(Decimal::from(1)),
+            currency: None,
+            to: None,
         };
         assert_eq!(tx_db.is_tx_exists(&tx.tx), false);
-        tx_db.add_tx(&tx);
+        tx_db.add_tx(&tx, Decimal::ZERO);
         assert_eq!(tx_db.is_tx_exists(&tx.tx), true);
-        tx_db.remove_tx(&tx.tx);
-        assert_eq!(tx_db.is_tx_exists(&tx.tx), false);
+        assert_eq!(tx_db.get_tx(&tx.tx).unwrap().tx, tx);
+        assert_eq!(tx_db.get_tx(&tx.tx).unwrap().state, TxState::Processed);
+        assert_eq!(tx_db.transactions().len(), 1);
     }
 
     #[test]
     fn basic_disputes_functionality() {
-        let mut tx_db = TransactionDatabase{db: BTreeMap::new(), disputes: BTreeSet::new()};
+        let mut tx_db = TransactionDatabase::new();
         let tx = Transaction{
             tx_type: TransactionType::Deposit,
             client: 1,
             tx: 1,
             amount: Some(Decimal::from(1)),
+            currency: None,
+            to: None,
         };
-        assert_eq!(tx_db.is_under_dispute(&tx.tx), false);
-        tx_db.create_dispute(&tx.tx);
-        assert_eq!(tx_db.is_under_dispute(&tx.tx), true);
-        tx_db.remove_dispute(&tx.tx);
-        assert_eq!(tx_db.is_under_dispute(&tx.tx), false);
+        tx_db.add_tx(&tx, Decimal::ZERO);
+        tx_db.set_state(&tx.tx, TxState::Disputed);
+        assert_eq!(tx_db.get_tx(&tx.tx).unwrap().state, TxState::Disputed);
+        tx_db.set_state(&tx.tx, TxState::ChargedBack);
+        assert_eq!(tx_db.get_tx(&tx.tx).unwrap().state, TxState::ChargedBack);
     }
-}
-
 
+    #[test]
+    fn state_transitions_functionality() {
+        let never = RedisputePolicy::Never;
+        let after_resolve = RedisputePolicy::AfterResolve;
+        assert_eq!(TxState::Processed.transition(TxState::Disputed, never), Ok(TxState::Disputed));
+        assert_eq!(TxState::Processed.transition(TxState::Resolved, never),
+            Err(RejectReason::NotUnderDispute));
+        assert_eq!(TxState::Processed.transition(TxState::ChargedBack, never),
+            Err(RejectReason::NotUnderDispute));
+        assert_eq!(TxState::Disputed.transition(TxState::Disputed, never),
+            Err(RejectReason::AlreadyDisputed));
+        assert_eq!(TxState::Disputed.transition(TxState::Resolved, never), Ok(TxState::Resolved));
+        assert_eq!(TxState::Disputed.transition(TxState::ChargedBack, never),
+            Ok(TxState::ChargedBack));
+        assert_eq!(TxState::Resolved.transition(TxState::Disputed, never),
+            Err(RejectReason::AlreadyDisputed));
+        assert_eq!(TxState::Resolved.transition(TxState::Disputed, after_resolve),
+            Ok(TxState::Disputed));
+        assert_eq!(TxState::Resolved.transition(TxState::Resolved, after_resolve),
+            Err(RejectReason::NotUnderDispute));
+        assert_eq!(TxState::ChargedBack.transition(TxState::Disputed, after_resolve),
+            Err(RejectReason::AlreadyChargedBack));
+        assert_eq!(TxState::Disputed.transition(TxState::PartiallyChargedBack, never),
+            Ok(TxState::PartiallyChargedBack));
+        assert_eq!(TxState::PartiallyChargedBack.transition(TxState::Disputed, never),
+            Ok(TxState::Disputed));
+        assert_eq!(TxState::PartiallyChargedBack.transition(TxState::ChargedBack, never),
+            Err(RejectReason::NotUnderDispute));
+        assert_eq!(TxState::Processed.transition(TxState::Processed, never),
+            Err(RejectReason::DuplicateTxId));
+    }
+}
@@ -1,32 +1,249 @@
-use std::env;
 use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::iter;
+use std::str::FromStr;
 
-pub fn get_transaction_reader() -> csv::Reader<File> {
-    let csv_reader: csv::Reader<File>;
-    {
-        let fpath: String;
-        {
-            let args: Vec<String> = env::args().collect();
-            if args.len() != 2 {
-                panic!("User error. Run toy payment engine as: $ cargo run -- transactions.csv");
-            }
-            fpath = args[1].to_string();
-        }
+use crate::rejects::RejectCause;
+use crate::tx::{PrecisionPolicy, Transaction};
 
-        let csv_reader_res = csv::ReaderBuilder::new()
-            .has_headers(true)
-            .delimiter(b',')
-            .flexible(true)
-            .trim(csv::Trim::All)
-            .from_path(&fpath);
+/// Input path which reads the transactions from stdin.
+pub const STDIN_INPUT: &str = "-";
 
-        if csv_reader_res.is_ok() {
-            csv_reader = csv_reader_res.unwrap();
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum InputFormat {
+    /// CSV with a `type,client,tx,amount` header row.
+    Csv,
+    /// One JSON array of transaction objects.
+    Json,
+    /// One transaction object per line.
+    Ndjson,
+}
+
+impl InputFormat {
+    /// JSON for `.json` files, NDJSON for `.ndjson` and `.jsonl` files, CSV otherwise.
+    pub fn from_path(fpath: &str) -> InputFormat {
+        if fpath.ends_with(".json") {
+            InputFormat::Json
+        } else if fpath.ends_with(".ndjson") || fpath.ends_with(".jsonl") {
+            InputFormat::Ndjson
         } else {
-            panic!("file {} not found", &fpath);
+            InputFormat::Csv
         }
     }
-    csv_reader
+}
+
+impl FromStr for InputFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            "ndjson" => Ok(InputFormat::Ndjson),
+            _ => Err(()),
+        }
+    }
+}
+
+/// One row of an input: the validated transaction or the reason why the row is unusable.
+#[derive(Debug)]
+pub struct InputRow {
+    /// Line of the row, the position of the element for a JSON array.
+    pub line: u64,
+    /// The row as it appears in the input without its line terminator, the JSON object for
+    /// an element of a JSON array.
+    pub record: String,
+    pub tx: Result<Transaction, RejectCause>,
+}
+
+/// Reads the rows of an input in the given format, amounts are validated with the
+/// precision policy.
+///
+/// A JSON array is read at once and must be well-formed as a whole, its elements are
+/// validated one by one. CSV and NDJSON inputs are streamed, reading stops at the first
+/// IO error.
+pub fn read_input(fpath: &str, format: InputFormat, precision_policy: PrecisionPolicy)
+        -> io::Result<Box<dyn Iterator<Item = io::Result<InputRow>>>> {
+    let input = open_input(fpath)?;
+    Ok(match format {
+        InputFormat::Csv => Box::new(csv_rows(input, precision_policy)?),
+        InputFormat::Json => Box::new(json_rows(input, precision_policy)?.map(Ok)),
+        InputFormat::Ndjson => Box::new(ndjson_rows(BufReader::new(input), precision_policy)),
+    })
+}
+
+/// Reader which keeps the bytes read from its input until the record they belong to is
+/// taken, so that rows can be reported as they were.
+struct RecordingReader<R> {
+    input: R,
+    buffer: Vec<u8>,
+    /// Byte offset of the start of the buffer in the input.
+    offset: u64,
+}
+
+impl<R: Read> Read for RecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.input.read(buf)?;
+        self.buffer.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<R> RecordingReader<R> {
+    fn new(input: R) -> RecordingReader<R> {
+        RecordingReader{input, buffer: Vec::new(), offset: 0}
+    }
+
+    /// Returns the input between two byte offsets without surrounding line terminators and
+    /// the number of lines skipped before it, and forgets everything before the end.
+    fn take(&mut self, start: u64, end: u64) -> (u64, String) {
+        let from = (start.saturating_sub(self.offset) as usize).min(self.buffer.len());
+        let to = (end.saturating_sub(self.offset) as usize).clamp(from, self.buffer.len());
+        let raw = String::from_utf8_lossy(&self.buffer[from..to]);
+        let record = raw.trim_start_matches(['\r', '\n']);
+        let skipped = raw[..raw.len() - record.len()].matches('\n').count() as u64;
+        let record = record.trim_end_matches(['\r', '\n']).to_string();
+        self.buffer.drain(..to);
+        self.offset += to as u64;
+        (skipped, record)
+    }
+}
+
+fn csv_rows<R: Read>(input: R, precision_policy: PrecisionPolicy)
+        -> io::Result<impl Iterator<Item = io::Result<InputRow>>> {
+    let mut tx_reader = transaction_reader(RecordingReader::new(input));
+    let headers = tx_reader.headers()?.clone();
+    let mut failed = false;
+    Ok(iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        let mut record = csv::StringRecord::new();
+        let start = tx_reader.position().clone();
+        let res = tx_reader.read_record(&mut record);
+        let end = tx_reader.position().byte();
+        // the reader counts the lines of a record from the end of the previous one
+        let (skipped, raw) = tx_reader.get_mut().take(start.byte(), end);
+        let line = start.line() + skipped;
+        match res {
+            Ok(false) => None,
+            Ok(true) => Some(Ok(InputRow{
+                line,
+                record: raw,
+                tx: parse_record(&record, &headers, precision_policy),
+            })),
+            Err(err) if err.is_io_error() => {
+                failed = true;
+                Some(Err(err.into()))
+            },
+            Err(err) => Some(Ok(InputRow{
+                line,
+                record: raw,
+                tx: Err(RejectCause::Parse(err)),
+            })),
+        }
+    }))
+}
+
+fn json_rows<R: Read>(input: R, precision_policy: PrecisionPolicy)
+        -> io::Result<impl Iterator<Item = InputRow>> {
+    let values: Vec<serde_json::Value> = serde_json::from_reader(input)?;
+    Ok(values.into_iter().enumerate().map(move |(idx, value)| InputRow{
+        line: idx as u64 + 1,
+        record: value.to_string(),
+        tx: serde_json::from_value(value).map_err(RejectCause::ParseJson)
+            .and_then(|tx| validated(tx, precision_policy)),
+    }))
+}
+
+fn ndjson_rows<R: BufRead>(mut input: R, precision_policy: PrecisionPolicy)
+        -> impl Iterator<Item = io::Result<InputRow>> {
+    let mut buffer = Vec::new();
+    let mut line = 0;
+    let mut failed = false;
+    iter::from_fn(move || {
+        while !failed {
+            buffer.clear();
+            match input.read_until(b'\n', &mut buffer) {
+                Ok(0) => return None,
+                Ok(_) => line += 1,
+                Err(err) => {
+                    failed = true;
+                    return Some(Err(err));
+                },
+            }
+            let raw = buffer.strip_suffix(b"\n").unwrap_or(&buffer);
+            let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+            if raw.trim_ascii().is_empty() {
+                continue;
+            }
+            let tx = match std::str::from_utf8(raw) {
+                Ok(json) => parse_json(json, precision_policy),
+                Err(err) => Err(RejectCause::ParseJson(serde_json::Error::io(
+                    io::Error::new(io::ErrorKind::InvalidData, err)))),
+            };
+            return Some(Ok(InputRow{line, record: String::from_utf8_lossy(raw).into_owned(), tx}));
+        }
+        None
+    })
+}
+
+fn open_input(fpath: &str) -> io::Result<Box<dyn Read>> {
+    if fpath == STDIN_INPUT {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(File::open(fpath)?))
+    }
+}
+
+pub fn get_transaction_reader(fpath: &str) -> io::Result<csv::Reader<Box<dyn Read>>> {
+    Ok(transaction_reader(open_input(fpath)?))
+}
+
+fn transaction_reader_builder() -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder.has_headers(true)
+        .delimiter(b',')
+        .flexible(true)
+        .trim(csv::Trim::All);
+    builder
+}
+
+/// CSV reader for transactions with a header row, surrounding whitespace is ignored.
+pub fn transaction_reader<R: Read>(input: R) -> csv::Reader<R> {
+    transaction_reader_builder().from_reader(input)
+}
+
+/// Reads one line of a CSV stream without the header row like `transaction_reader` does,
+/// `None` for an empty line.
+pub fn read_line_record(line: &[u8]) -> Option<csv::Result<csv::StringRecord>> {
+    transaction_reader_builder().has_headers(false).from_reader(line).records().next()
+}
+
+fn validated(mut tx: Transaction, precision_policy: PrecisionPolicy) -> Result<Transaction, RejectCause> {
+    match tx.validate_with(precision_policy) {
+        Ok(()) => Ok(tx),
+        Err(err) => Err(RejectCause::Validation(err)),
+    }
+}
+
+/// Deserializes and validates one CSV record.
+pub fn parse_record(record: &csv::StringRecord, headers: &csv::StringRecord,
+        precision_policy: PrecisionPolicy) -> Result<Transaction, RejectCause> {
+    match record.deserialize::<Transaction>(Some(headers)) {
+        Ok(tx) => validated(tx, precision_policy),
+        Err(err) => Err(RejectCause::Parse(err)),
+    }
+}
+
+/// Deserializes and validates one JSON transaction object.
+pub fn parse_json(json: &str, precision_policy: PrecisionPolicy) -> Result<Transaction, RejectCause> {
+    match serde_json::from_str::<Transaction>(json) {
+        Ok(tx) => validated(tx, precision_policy),
+        Err(err) => Err(RejectCause::ParseJson(err)),
+    }
 }
 
 #[cfg(test)]
@@ -35,6 +252,7 @@ mod tests {
     use crate::tx::{Transaction, TransactionType};
 
     #[test]
+    #[allow(clippy::unnecessary_unwrap)]
     fn basic_reader_functionality() {
         let data = "type,   client,   tx,   amount\n
           deposit,     1,    2,      3.0\n
@@ -52,7 +270,7 @@ mod tests {
         for row in tx_reader.deserialize::<Transaction>() {
             if row.is_ok() {
                 let mut tx = row.unwrap();
-                if tx.validate() {
+                if tx.validate().is_ok() {
                     tx_vec.push(tx);
                 }
             }
@@ -61,24 +279,187 @@ mod tests {
           tx_type: TransactionType::Deposit,
           client: 1,
           tx: 2,
-          amount: Some(Decimal::from(3))
+          amount: Some(Decimal::from(3)),
+          currency: None,
+          to: None,
         };
         let expected_tx_1 = Transaction {
           tx_type: TransactionType::Withdrawal,
           client: 4,
           tx: 5,
-          amount: Some(Decimal::from(6))
+          amount: Some(Decimal::from(6)),
+          currency: None,
+          to: None,
         };
         let expected_tx_2 = Transaction {
           tx_type: TransactionType::Chargeback,
           client: 7,
           tx: 8,
-          amount: None
+          amount: None,
+          currency: None,
+          to: None,
         };
         assert_eq!(tx_vec.len(), 3);
         assert_eq!(tx_vec[0], expected_tx_0);
         assert_eq!(tx_vec[1], expected_tx_1);
         assert_eq!(tx_vec[2], expected_tx_2);
     }
-}
 
+    #[test]
+    fn json_reader_functionality() {
+        use std::env;
+        use std::fs;
+        use crate::rejects::RejectCause;
+        use crate::tx::PrecisionPolicy;
+        use crate::tx_reader::{read_input, InputFormat};
+
+        let fpath = env::temp_dir().join("toy_payments_engine_reader.json");
+        let fpath = fpath.to_str().unwrap();
+        assert_eq!(InputFormat::from_path(fpath), InputFormat::Json);
+        fs::write(fpath, r#"[
+            {"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"},
+            {"type": "withdrawal", "client": 1, "tx": 2, "amount": 0.25},
+            {"type": "dispute", "client": 1, "tx": 1, "amount": null},
+            {"type": "deposit", "client": 1, "tx": 3},
+            {"type": "refund", "client": 1, "tx": 4, "amount": "1"}
+        ]"#).unwrap();
+        let rows: Vec<_> = read_input(fpath, InputFormat::Json, PrecisionPolicy::default()).unwrap()
+            .map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0].tx.as_ref().unwrap().amount, Some(Decimal::new(15, 1)));
+        assert_eq!(rows[1].tx.as_ref().unwrap().amount, Some(Decimal::new(25, 2)));
+        assert_eq!(rows[2].tx.as_ref().unwrap().tx_type, TransactionType::Dispute);
+        assert!(matches!(rows[3].tx, Err(RejectCause::Validation(_))));
+        assert!(matches!(rows[4].tx, Err(RejectCause::ParseJson(_))));
+        assert_eq!(rows[4].line, 5);
+
+        fs::write(fpath, r#"[{"type": "deposit""#).unwrap();
+        assert!(read_input(fpath, InputFormat::Json, PrecisionPolicy::default()).is_err());
+        fs::remove_file(fpath).unwrap();
+    }
+
+    #[test]
+    fn ndjson_reader_functionality() {
+        use std::env;
+        use std::fs;
+        use crate::rejects::RejectCause;
+        use crate::tx::PrecisionPolicy;
+        use crate::tx_reader::{read_input, InputFormat};
+
+        let fpath = env::temp_dir().join("toy_payments_engine_reader.ndjson");
+        let fpath = fpath.to_str().unwrap();
+        assert_eq!(InputFormat::from_path(fpath), InputFormat::Ndjson);
+        fs::write(fpath, "{\"type\": \"deposit\", \"client\": 2, \"tx\": 7, \"amount\": \"3\"}\n\n\
+            not json\n{\"type\": \"chargeback\", \"client\": 2, \"tx\": 7}\n\
+            {\"type\": \"deposit\", \"client\": 2, \"tx\": 8, \"amount\": \"0.00001\"}\n").unwrap();
+        let rows: Vec<_> = read_input(fpath, InputFormat::Ndjson, PrecisionPolicy::Reject).unwrap()
+            .map(Result::unwrap).collect();
+        fs::remove_file(fpath).unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].tx.as_ref().unwrap(), &Transaction {
+          tx_type: TransactionType::Deposit,
+          client: 2,
+          tx: 7,
+          amount: Some(Decimal::from(3)),
+          currency: None,
+          to: None,
+        });
+        assert_eq!((rows[1].line, rows[1].record.as_str()), (3, "not json"));
+        assert!(matches!(rows[1].tx, Err(RejectCause::ParseJson(_))));
+        assert_eq!(rows[2].line, 4);
+        assert_eq!(rows[2].tx.as_ref().unwrap().tx_type, TransactionType::Chargeback);
+        assert_eq!(rows[3].tx.as_ref().unwrap_err().to_string(), "amount has more than 4 decimal places");
+    }
+
+    #[test]
+    fn csv_raw_record_functionality() {
+        use std::env;
+        use std::fs;
+        use crate::rejects::RejectCause;
+        use crate::tx::PrecisionPolicy;
+        use crate::tx_reader::{read_input, InputFormat};
+
+        let fpath = env::temp_dir().join("toy_payments_engine_raw.csv");
+        let mut data = b"type, client, tx, amount\r\ndeposit,  1, 1,\"1.5\"\r\n\n".to_vec();
+        data.extend_from_slice(b"withdrawal, 1, 2, \xff\nwithdrawal, 1, x, 1\n");
+        fs::write(&fpath, data).unwrap();
+        let rows: Vec<_> = read_input(fpath.to_str().unwrap(), InputFormat::Csv, PrecisionPolicy::default())
+            .unwrap().map(Result::unwrap).collect();
+        fs::remove_file(&fpath).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!((rows[0].line, rows[0].record.as_str()), (2, "deposit,  1, 1,\"1.5\""));
+        assert_eq!(rows[0].tx.as_ref().unwrap().amount, Some(Decimal::new(15, 1)));
+        assert_eq!((rows[1].line, rows[1].record.as_str()), (4, "withdrawal, 1, 2, \u{fffd}"));
+        assert!(matches!(rows[1].tx, Err(RejectCause::Parse(_))));
+        assert_eq!((rows[2].line, rows[2].record.as_str()), (5, "withdrawal, 1, x, 1"));
+    }
+
+    #[test]
+    fn io_error_reader_functionality() {
+        use std::env;
+        use std::io::{self, BufReader, Read};
+        use crate::rejects::RejectCause;
+        use crate::tx::PrecisionPolicy;
+        use crate::tx_reader::{csv_rows, ndjson_rows, read_input, InputFormat};
+
+        /// Returns its data, then fails on every read.
+        struct FailingReader(&'static [u8]);
+
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::other("disk failure"));
+                }
+                let len = self.0.len().min(buf.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let dir = env::temp_dir();
+        assert!(read_input(dir.to_str().unwrap(), InputFormat::Csv, PrecisionPolicy::default()).is_err());
+        let rows: Vec<_> = csv_rows(FailingReader(b"type,client,tx,amount\ndeposit,1,1,1\n"),
+            PrecisionPolicy::default()).unwrap().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].as_ref().unwrap().tx.is_ok());
+        assert_eq!(rows[1].as_ref().unwrap_err().to_string(), "disk failure");
+
+        // a directory opens but every read fails
+        let rows: Vec<_> = read_input(dir.to_str().unwrap(), InputFormat::Ndjson, PrecisionPolicy::default())
+            .unwrap().collect();
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].is_err());
+        let rows: Vec<_> = ndjson_rows(BufReader::new(FailingReader(
+            b"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"1\"}\n\xff\n")),
+            PrecisionPolicy::default()).collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].as_ref().unwrap().tx.is_ok());
+        assert_eq!(rows[1].as_ref().unwrap().line, 2);
+        assert!(matches!(rows[1].as_ref().unwrap().tx, Err(RejectCause::ParseJson(_))));
+        assert_eq!(rows[2].as_ref().unwrap_err().to_string(), "disk failure");
+    }
+
+    #[test]
+    fn file_reader_functionality() {
+        use std::env;
+        use std::fs;
+        use crate::tx_reader::get_transaction_reader;
+
+        let fpath = env::temp_dir().join("toy_payments_engine_reader.csv");
+        fs::write(&fpath, "type, client, tx, amount\ndeposit, 1, 1, 1.5\n").unwrap();
+        let mut tx_reader = get_transaction_reader(fpath.to_str().unwrap()).unwrap();
+        let tx_vec: Vec<Transaction> = tx_reader.deserialize().flatten().collect();
+        fs::remove_file(&fpath).unwrap();
+        let expected_tx = Transaction {
+          tx_type: TransactionType::Deposit,
+          client: 1,
+          tx: 1,
+          amount: Some(Decimal::new(15, 1)),
+          currency: None,
+          to: None,
+        };
+        assert_eq!(tx_vec, vec![expected_tx]);
+        assert!(get_transaction_reader(fpath.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tx::Transaction;
+
+/// One accepted transaction, numbered in the order it was applied.
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalRecord {
+    pub seq: u64,
+    pub tx: Transaction,
+}
+
+/// Append-only write-ahead log of accepted transactions, one JSON record per line.
+///
+/// Every record is fsynced before `append` returns. A record without the terminating
+/// newline was torn by a crash, it is dropped when the log is opened again.
+pub struct WriteAheadLog {
+    file: File,
+}
+
+impl WriteAheadLog {
+    /// Opens (or creates) the log and returns it with the records it holds.
+    pub fn open<P: AsRef<Path>>(fpath: P) -> io::Result<(WriteAheadLog, Vec<WalRecord>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(fpath)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        for line in content.split_inclusive(|byte| *byte == b'\n') {
+            if !line.ends_with(b"\n") {
+                // torn final record
+                break;
+            }
+            let record: WalRecord = match serde_json::from_slice(line) {
+                Ok(val) => val,
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("corrupted write-ahead log record {}: {}", records.len() + 1, err))),
+            };
+            records.push(record);
+            valid_len += line.len();
+        }
+        if valid_len < content.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok((WriteAheadLog{file}, records))
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+
+    /// Drops all records, used once they are covered by a snapshot.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use rust_decimal::Decimal;
+    use crate::tx::{Transaction, TransactionType};
+    use crate::wal::{WalRecord, WriteAheadLog};
+
+    fn get_test_record(seq: u64) -> WalRecord {
+        WalRecord {
+            seq,
+            tx: Transaction{
+                tx_type: TransactionType::Deposit,
+                client: 1,
+                tx: seq as u32,
+                amount: Some(Decimal::new(15, 1)),
+                currency: None,
+                to: None,
+            },
+        }
+    }
+
+    #[test]
+    fn torn_record_functionality() {
+        let fpath = env::temp_dir().join("toy_payments_engine_torn.wal");
+        let _ = fs::remove_file(&fpath);
+        {
+            let (mut wal, records) = WriteAheadLog::open(&fpath).unwrap();
+            assert!(records.is_empty());
+            wal.append(&get_test_record(1)).unwrap();
+            wal.append(&get_test_record(2)).unwrap();
+        }
+        {
+            let mut file = OpenOptions::new().append(true).open(&fpath).unwrap();
+            file.write_all(b"{\"seq\":3,\"tx\":{\"type\":\"dep").unwrap();
+        }
+        {
+            let (mut wal, records) = WriteAheadLog::open(&fpath).unwrap();
+            assert_eq!(records, vec![get_test_record(1), get_test_record(2)]);
+            wal.append(&get_test_record(3)).unwrap();
+        }
+        let (mut wal, records) = WriteAheadLog::open(&fpath).unwrap();
+        assert_eq!(records, vec![get_test_record(1), get_test_record(2), get_test_record(3)]);
+        wal.truncate().unwrap();
+        drop(wal);
+        let (_, records) = WriteAheadLog::open(&fpath).unwrap();
+        assert!(records.is_empty());
+        fs::remove_file(&fpath).unwrap();
+    }
+}